@@ -5,6 +5,7 @@
 extern crate libc;
 extern crate libkvm;
 
+use libkvm::exit::{IoDirection, IoExit, MmioExit, VcpuExit};
 use libkvm::linux::kvm_bindings::*;
 use libkvm::mem::MemorySlot;
 use libkvm::system::*;
@@ -59,56 +60,48 @@ fn main() {
     setup_msrs(&kvm, &vcpu);
 
     loop {
-        vcpu.run().unwrap();
-        let mut kvm_run = vcpu.kvm_run_mut();
-        match kvm_run.exit_reason {
-            KVM_EXIT_HLT => {
+        match vcpu.run_exit().unwrap() {
+            VcpuExit::Hlt => {
                 println!("Halt");
                 break;
             }
-            KVM_EXIT_MMIO => {
-                handle_mmio(&mut kvm_run);
+            VcpuExit::Mmio(mut mmio) => {
+                handle_mmio(&mut mmio);
             }
-            KVM_EXIT_IO => {
-                handle_io_port(&kvm_run);
+            VcpuExit::Io(io) => {
+                handle_io_port(&io);
             }
-            _ => {
-                panic!("Not supported exit reason: {}", kvm_run.exit_reason);
+            exit => {
+                panic!("Not supported exit reason: {:?}", exit);
             }
         }
     }
 }
 
-fn handle_io_port(kvm_run: &kvm_run) {
-    let io = unsafe { &kvm_run.__bindgen_anon_1.io };
-
-    if io.direction == KVM_EXIT_IO_OUT as u8 && io.port == 42 {
-        let data_addr = kvm_run as *const _ as u64 + io.data_offset;
-        let data = unsafe { std::slice::from_raw_parts(data_addr as *const u8, io.size as usize) };
-        io::stdout().write(data).unwrap();
+fn handle_io_port(io: &IoExit) {
+    if io.direction() == IoDirection::Out && io.port() == 42 {
+        io::stdout().write_all(io.data()).unwrap();
     }
 }
 
-fn handle_mmio(kvm_run: &mut kvm_run) {
-    let mmio = unsafe { &mut kvm_run.__bindgen_anon_1.mmio };
-    let data = &mut mmio.data as *mut _ as *mut u64;
-
-    if mmio.len == 8 {
-        if mmio.is_write == 0 {
-            unsafe {
-                *data = 0x1000;
-                println!(
-                    "MMIO read address: 0x{:x}, data: 0x{:x}",
-                    mmio.phys_addr, *data
-                );
-            }
+fn handle_mmio(mmio: &mut MmioExit) {
+    if mmio.len() == 8 {
+        if !mmio.is_write() {
+            let data: u64 = 0x1000;
+            mmio.complete_read(&data.to_le_bytes()).unwrap();
+            println!(
+                "MMIO read address: 0x{:x}, data: 0x{:x}",
+                mmio.phys_addr(),
+                data
+            );
         } else {
-            unsafe {
-                println!(
-                    "MMIO write address: 0x{:x}, data: 0x{:x}",
-                    mmio.phys_addr, *data
-                );
-            }
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(mmio.data());
+            println!(
+                "MMIO write address: 0x{:x}, data: 0x{:x}",
+                mmio.phys_addr(),
+                u64::from_le_bytes(bytes)
+            );
         }
     }
 }
@@ -0,0 +1,433 @@
+// Copyright (C) 2018, Allison Randal
+//
+// Licensed under LGPL version 2 or any later version.
+
+//! Types for probing and enabling optional KVM capabilities.
+//!
+//! KVM reports the features it supports as capabilities, which are queried
+//! with `KVM_CHECK_EXTENSION` on the system or virtual machine filehandle.
+//! The answer is an integer, which is 0 for unsupported capabilities, and
+//! for some capabilities is a limit such as the number of memory slots.
+//!
+//! Some KVM features are switched off by default and must be enabled with
+//! `KVM_ENABLE_CAP`, either on the virtual machine filehandle or on a
+//! single virtual CPU. Each capability interprets the `args` of the raw
+//! `kvm_enable_cap` structure differently, so the `VmCap` and `VcpuCap`
+//! enums describe the supported capabilities with typed arguments, and
+//! check them before they are passed to KVM.
+
+use std::io::{Error, ErrorKind};
+use std::slice;
+
+use linux::kvm_bindings::*;
+
+/// The maximum number of GSI routes in KVM, which also limits the number of
+/// IOAPIC pins reserved by a split irqchip.
+const KVM_MAX_IRQ_ROUTES: u32 = 4096;
+
+/// All flags accepted by `VmCap::X2ApicApi`.
+pub const X2APIC_API_VALID_FLAGS: u32 =
+    KVM_X2APIC_API_USE_32BIT_IDS | KVM_X2APIC_API_DISABLE_BROADCAST_QUIRK;
+
+/// All hypercalls accepted by `VmCap::ExitHypercall`.
+pub const EXIT_HYPERCALL_VALID_MASK: u64 = 1 << KVM_HC_MAP_GPA_RANGE;
+
+/// All flags accepted by `VmCap::UserSpaceMsr`.
+pub const MSR_EXIT_REASON_VALID_FLAGS: u32 =
+    KVM_MSR_EXIT_REASON_INVAL | KVM_MSR_EXIT_REASON_UNKNOWN | KVM_MSR_EXIT_REASON_FILTER;
+
+/// A capability that can be enabled on a virtual machine with
+/// `VirtualMachine::enable_cap`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VmCap {
+    /// Creates the local APICs in the kernel, while the PIC and IOAPIC are
+    /// emulated in userspace. The first `ioapic_pins` GSIs are reserved
+    /// for the userspace IOAPIC, whose interrupts are delivered to the
+    /// kernel as MSIs or with `VcpuExit::IoapicEoi` for level-triggered
+    /// pins. Must be enabled before any virtual CPUs are created, instead
+    /// of `VirtualMachine::create_irq_chip`.
+    SplitIrqchip { ioapic_pins: u32 },
+    /// Changes how x2APIC IDs are handled by the in-kernel local APICs,
+    /// with the `KVM_X2APIC_API_*` flags.
+    X2ApicApi(u32),
+    /// Disables legacy KVM behaviour that differs from real hardware, with
+    /// a mask of the `KVM_X86_QUIRK_*` flags. The mask is passed to KVM
+    /// unchecked, as newer kernels define more quirks.
+    DisableQuirks(u64),
+    /// Exits to userspace with `VcpuExit::Hypercall` for the given
+    /// hypercalls, with a mask of `1 << KVM_HC_*` bits.
+    ExitHypercall(u64),
+    /// Exits to userspace with `VcpuExit::X86Rdmsr` and
+    /// `VcpuExit::X86Wrmsr` for guest `rdmsr` and `wrmsr` instructions that
+    /// KVM would otherwise fail, with the `KVM_MSR_EXIT_REASON_*` flags
+    /// selecting which failures exit.
+    UserSpaceMsr(u32),
+    /// Sets the highest virtual CPU id (plus one) that can be used with
+    /// `VirtualMachine::create_vcpu_with_id`, which must not be 0. Must be
+    /// enabled before any virtual CPUs are created.
+    MaxVcpuId(u32),
+}
+
+impl VmCap {
+    /// Returns the `KVM_CAP_*` number of the capability.
+    pub fn cap(&self) -> u32 {
+        match *self {
+            VmCap::SplitIrqchip { .. } => KVM_CAP_SPLIT_IRQCHIP,
+            VmCap::X2ApicApi(_) => KVM_CAP_X2APIC_API,
+            VmCap::DisableQuirks(_) => KVM_CAP_DISABLE_QUIRKS,
+            VmCap::ExitHypercall(_) => KVM_CAP_EXIT_HYPERCALL,
+            VmCap::UserSpaceMsr(_) => KVM_CAP_X86_USER_SPACE_MSR,
+            VmCap::MaxVcpuId(_) => KVM_CAP_MAX_VCPU_ID,
+        }
+    }
+
+    /// Checks the arguments of the capability, and converts it to the raw
+    /// structure passed to `KVM_ENABLE_CAP`. Fails with
+    /// `ErrorKind::InvalidInput` if an argument is out of range, or sets
+    /// flags that KVM does not define for the capability.
+    pub fn to_raw(&self) -> Result<kvm_enable_cap, Error> {
+        let (arg, valid) = match *self {
+            VmCap::SplitIrqchip { ioapic_pins } => {
+                (u64::from(ioapic_pins), ioapic_pins <= KVM_MAX_IRQ_ROUTES)
+            }
+            VmCap::X2ApicApi(flags) => (u64::from(flags), flags & !X2APIC_API_VALID_FLAGS == 0),
+            VmCap::DisableQuirks(quirks) => (quirks, true),
+            VmCap::ExitHypercall(mask) => (mask, mask & !EXIT_HYPERCALL_VALID_MASK == 0),
+            VmCap::UserSpaceMsr(flags) => {
+                (u64::from(flags), flags & !MSR_EXIT_REASON_VALID_FLAGS == 0)
+            }
+            VmCap::MaxVcpuId(max) => (u64::from(max), max > 0),
+        };
+        if !valid {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "invalid argument for KVM capability",
+            ));
+        }
+        let mut cap = kvm_enable_cap {
+            cap: self.cap(),
+            ..Default::default()
+        };
+        cap.args[0] = arg;
+        Ok(cap)
+    }
+}
+
+/// A capability that can be enabled on a single virtual CPU with
+/// `VirtualCPU::enable_cap`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VcpuCap {
+    /// Enables the Hyper-V synthetic interrupt controller, which requires
+    /// an in-kernel local APIC.
+    HypervSynic,
+    /// Enables the Hyper-V synthetic interrupt controller, with the message
+    /// and event flag pages handled as specified by Hyper-V rather than
+    /// the older KVM behaviour.
+    HypervSynic2,
+    /// Limits the Hyper-V features available to the guest to the ones
+    /// advertised in its CPUID, when set to true.
+    HypervEnforceCpuid(bool),
+    /// Limits the KVM paravirtual features available to the guest to the
+    /// ones advertised in its CPUID, when set to true.
+    EnforcePvFeatureCpuid(bool),
+}
+
+impl VcpuCap {
+    /// Returns the `KVM_CAP_*` number of the capability.
+    pub fn cap(&self) -> u32 {
+        match *self {
+            VcpuCap::HypervSynic => KVM_CAP_HYPERV_SYNIC,
+            VcpuCap::HypervSynic2 => KVM_CAP_HYPERV_SYNIC2,
+            VcpuCap::HypervEnforceCpuid(_) => KVM_CAP_HYPERV_ENFORCE_CPUID,
+            VcpuCap::EnforcePvFeatureCpuid(_) => KVM_CAP_ENFORCE_PV_FEATURE_CPUID,
+        }
+    }
+
+    /// Converts the capability to the raw structure passed to
+    /// `KVM_ENABLE_CAP`.
+    pub fn to_raw(&self) -> kvm_enable_cap {
+        let mut cap = kvm_enable_cap {
+            cap: self.cap(),
+            ..Default::default()
+        };
+        match *self {
+            VcpuCap::HypervSynic | VcpuCap::HypervSynic2 => {}
+            VcpuCap::HypervEnforceCpuid(enforce) | VcpuCap::EnforcePvFeatureCpuid(enforce) => {
+                cap.args[0] = u64::from(enforce);
+            }
+        }
+        cap
+    }
+}
+
+// Defines the `Capability` enum, with one variant for each `KVM_CAP_*`
+// constant, and the conversions to and from the constants.
+macro_rules! define_capabilities {
+    ($($variant:ident => $constant:ident,)*) => {
+        /// A capability that can be probed with `KVMSystem::check_extension`
+        /// or `VirtualMachine::check_extension`. Each variant stands for the
+        /// `KVM_CAP_*` constant of the same name, and `Other` holds
+        /// capabilities that are not listed here.
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+        pub enum Capability {
+            $($variant,)*
+            Other(u32),
+        }
+
+        /// Every capability listed in `Capability`, in the order of the
+        /// `KVM_CAP_*` numbers.
+        const ALL_CAPABILITIES: &[Capability] = &[$(Capability::$variant,)*];
+
+        impl Capability {
+            /// Converts one of the `KVM_CAP_*` numbers to a `Capability`.
+            pub fn from_raw(cap: u32) -> Capability {
+                match cap {
+                    $($constant => Capability::$variant,)*
+                    other => Capability::Other(other),
+                }
+            }
+
+            /// Returns the `KVM_CAP_*` number of the capability.
+            pub fn to_raw(&self) -> u32 {
+                match *self {
+                    $(Capability::$variant => $constant,)*
+                    Capability::Other(other) => other,
+                }
+            }
+        }
+    };
+}
+
+define_capabilities! {
+    Irqchip => KVM_CAP_IRQCHIP,
+    Hlt => KVM_CAP_HLT,
+    MmuShadowCacheControl => KVM_CAP_MMU_SHADOW_CACHE_CONTROL,
+    UserMemory => KVM_CAP_USER_MEMORY,
+    SetTssAddr => KVM_CAP_SET_TSS_ADDR,
+    Vapic => KVM_CAP_VAPIC,
+    ExtCpuid => KVM_CAP_EXT_CPUID,
+    Clocksource => KVM_CAP_CLOCKSOURCE,
+    NrVcpus => KVM_CAP_NR_VCPUS,
+    NrMemslots => KVM_CAP_NR_MEMSLOTS,
+    Pit => KVM_CAP_PIT,
+    NopIoDelay => KVM_CAP_NOP_IO_DELAY,
+    PvMmu => KVM_CAP_PV_MMU,
+    MpState => KVM_CAP_MP_STATE,
+    CoalescedMmio => KVM_CAP_COALESCED_MMIO,
+    SyncMmu => KVM_CAP_SYNC_MMU,
+    Iommu => KVM_CAP_IOMMU,
+    DestroyMemoryRegionWorks => KVM_CAP_DESTROY_MEMORY_REGION_WORKS,
+    UserNmi => KVM_CAP_USER_NMI,
+    SetGuestDebug => KVM_CAP_SET_GUEST_DEBUG,
+    ReinjectControl => KVM_CAP_REINJECT_CONTROL,
+    IrqRouting => KVM_CAP_IRQ_ROUTING,
+    IrqInjectStatus => KVM_CAP_IRQ_INJECT_STATUS,
+    AssignDevIrq => KVM_CAP_ASSIGN_DEV_IRQ,
+    JoinMemoryRegionsWorks => KVM_CAP_JOIN_MEMORY_REGIONS_WORKS,
+    Mce => KVM_CAP_MCE,
+    Irqfd => KVM_CAP_IRQFD,
+    Pit2 => KVM_CAP_PIT2,
+    SetBootCpuId => KVM_CAP_SET_BOOT_CPU_ID,
+    PitState2 => KVM_CAP_PIT_STATE2,
+    Ioeventfd => KVM_CAP_IOEVENTFD,
+    SetIdentityMapAddr => KVM_CAP_SET_IDENTITY_MAP_ADDR,
+    XenHvm => KVM_CAP_XEN_HVM,
+    AdjustClock => KVM_CAP_ADJUST_CLOCK,
+    InternalErrorData => KVM_CAP_INTERNAL_ERROR_DATA,
+    VcpuEvents => KVM_CAP_VCPU_EVENTS,
+    S390Psw => KVM_CAP_S390_PSW,
+    PpcSegstate => KVM_CAP_PPC_SEGSTATE,
+    Hyperv => KVM_CAP_HYPERV,
+    HypervVapic => KVM_CAP_HYPERV_VAPIC,
+    HypervSpin => KVM_CAP_HYPERV_SPIN,
+    PciSegment => KVM_CAP_PCI_SEGMENT,
+    PpcPairedSingles => KVM_CAP_PPC_PAIRED_SINGLES,
+    IntrShadow => KVM_CAP_INTR_SHADOW,
+    Debugregs => KVM_CAP_DEBUGREGS,
+    X86RobustSinglestep => KVM_CAP_X86_ROBUST_SINGLESTEP,
+    PpcOsi => KVM_CAP_PPC_OSI,
+    PpcUnsetIrq => KVM_CAP_PPC_UNSET_IRQ,
+    EnableCap => KVM_CAP_ENABLE_CAP,
+    Xsave => KVM_CAP_XSAVE,
+    Xcrs => KVM_CAP_XCRS,
+    PpcGetPvinfo => KVM_CAP_PPC_GET_PVINFO,
+    PpcIrqLevel => KVM_CAP_PPC_IRQ_LEVEL,
+    AsyncPf => KVM_CAP_ASYNC_PF,
+    TscControl => KVM_CAP_TSC_CONTROL,
+    GetTscKhz => KVM_CAP_GET_TSC_KHZ,
+    PpcBookeSregs => KVM_CAP_PPC_BOOKE_SREGS,
+    SpaprTce => KVM_CAP_SPAPR_TCE,
+    PpcSmt => KVM_CAP_PPC_SMT,
+    PpcRma => KVM_CAP_PPC_RMA,
+    MaxVcpus => KVM_CAP_MAX_VCPUS,
+    PpcHior => KVM_CAP_PPC_HIOR,
+    PpcPapr => KVM_CAP_PPC_PAPR,
+    SwTlb => KVM_CAP_SW_TLB,
+    OneReg => KVM_CAP_ONE_REG,
+    S390Gmap => KVM_CAP_S390_GMAP,
+    TscDeadlineTimer => KVM_CAP_TSC_DEADLINE_TIMER,
+    S390Ucontrol => KVM_CAP_S390_UCONTROL,
+    SyncRegs => KVM_CAP_SYNC_REGS,
+    Pci23 => KVM_CAP_PCI_2_3,
+    KvmclockCtrl => KVM_CAP_KVMCLOCK_CTRL,
+    SignalMsi => KVM_CAP_SIGNAL_MSI,
+    PpcGetSmmuInfo => KVM_CAP_PPC_GET_SMMU_INFO,
+    S390Cow => KVM_CAP_S390_COW,
+    PpcAllocHtab => KVM_CAP_PPC_ALLOC_HTAB,
+    ReadonlyMem => KVM_CAP_READONLY_MEM,
+    IrqfdResample => KVM_CAP_IRQFD_RESAMPLE,
+    PpcBookeWatchdog => KVM_CAP_PPC_BOOKE_WATCHDOG,
+    PpcHtabFd => KVM_CAP_PPC_HTAB_FD,
+    S390CssSupport => KVM_CAP_S390_CSS_SUPPORT,
+    PpcEpr => KVM_CAP_PPC_EPR,
+    ArmPsci => KVM_CAP_ARM_PSCI,
+    ArmSetDeviceAddr => KVM_CAP_ARM_SET_DEVICE_ADDR,
+    DeviceCtrl => KVM_CAP_DEVICE_CTRL,
+    IrqMpic => KVM_CAP_IRQ_MPIC,
+    PpcRtas => KVM_CAP_PPC_RTAS,
+    IrqXics => KVM_CAP_IRQ_XICS,
+    ArmEl132bit => KVM_CAP_ARM_EL1_32BIT,
+    SpaprMultitce => KVM_CAP_SPAPR_MULTITCE,
+    ExtEmulCpuid => KVM_CAP_EXT_EMUL_CPUID,
+    HypervTime => KVM_CAP_HYPERV_TIME,
+    IoapicPolarityIgnored => KVM_CAP_IOAPIC_POLARITY_IGNORED,
+    EnableCapVm => KVM_CAP_ENABLE_CAP_VM,
+    S390Irqchip => KVM_CAP_S390_IRQCHIP,
+    IoeventfdNoLength => KVM_CAP_IOEVENTFD_NO_LENGTH,
+    VmAttributes => KVM_CAP_VM_ATTRIBUTES,
+    ArmPsci02 => KVM_CAP_ARM_PSCI_0_2,
+    PpcFixupHcall => KVM_CAP_PPC_FIXUP_HCALL,
+    PpcEnableHcall => KVM_CAP_PPC_ENABLE_HCALL,
+    CheckExtensionVm => KVM_CAP_CHECK_EXTENSION_VM,
+    S390UserSigp => KVM_CAP_S390_USER_SIGP,
+    S390VectorRegisters => KVM_CAP_S390_VECTOR_REGISTERS,
+    S390MemOp => KVM_CAP_S390_MEM_OP,
+    S390UserStsi => KVM_CAP_S390_USER_STSI,
+    S390Skeys => KVM_CAP_S390_SKEYS,
+    MipsFpu => KVM_CAP_MIPS_FPU,
+    MipsMsa => KVM_CAP_MIPS_MSA,
+    S390InjectIrq => KVM_CAP_S390_INJECT_IRQ,
+    S390IrqState => KVM_CAP_S390_IRQ_STATE,
+    PpcHwrng => KVM_CAP_PPC_HWRNG,
+    DisableQuirks => KVM_CAP_DISABLE_QUIRKS,
+    X86Smm => KVM_CAP_X86_SMM,
+    MultiAddressSpace => KVM_CAP_MULTI_ADDRESS_SPACE,
+    GuestDebugHwBps => KVM_CAP_GUEST_DEBUG_HW_BPS,
+    GuestDebugHwWps => KVM_CAP_GUEST_DEBUG_HW_WPS,
+    SplitIrqchip => KVM_CAP_SPLIT_IRQCHIP,
+    IoeventfdAnyLength => KVM_CAP_IOEVENTFD_ANY_LENGTH,
+    HypervSynic => KVM_CAP_HYPERV_SYNIC,
+    S390Ri => KVM_CAP_S390_RI,
+    SpaprTce64 => KVM_CAP_SPAPR_TCE_64,
+    ArmPmuV3 => KVM_CAP_ARM_PMU_V3,
+    VcpuAttributes => KVM_CAP_VCPU_ATTRIBUTES,
+    MaxVcpuId => KVM_CAP_MAX_VCPU_ID,
+    X2apicApi => KVM_CAP_X2APIC_API,
+    S390UserInstr0 => KVM_CAP_S390_USER_INSTR0,
+    MsiDevid => KVM_CAP_MSI_DEVID,
+    PpcHtm => KVM_CAP_PPC_HTM,
+    SpaprResizeHpt => KVM_CAP_SPAPR_RESIZE_HPT,
+    PpcMmuRadix => KVM_CAP_PPC_MMU_RADIX,
+    PpcMmuHashV3 => KVM_CAP_PPC_MMU_HASH_V3,
+    ImmediateExit => KVM_CAP_IMMEDIATE_EXIT,
+    MipsVz => KVM_CAP_MIPS_VZ,
+    MipsTe => KVM_CAP_MIPS_TE,
+    Mips64bit => KVM_CAP_MIPS_64BIT,
+    S390Gs => KVM_CAP_S390_GS,
+    S390Ais => KVM_CAP_S390_AIS,
+    SpaprTceVfio => KVM_CAP_SPAPR_TCE_VFIO,
+    X86GuestMwait => KVM_CAP_X86_GUEST_MWAIT,
+    ArmUserIrq => KVM_CAP_ARM_USER_IRQ,
+    S390CmmaMigration => KVM_CAP_S390_CMMA_MIGRATION,
+    PpcFwnmi => KVM_CAP_PPC_FWNMI,
+    PpcSmtPossible => KVM_CAP_PPC_SMT_POSSIBLE,
+    HypervSynic2 => KVM_CAP_HYPERV_SYNIC2,
+    HypervVpIndex => KVM_CAP_HYPERV_VP_INDEX,
+    S390AisMigration => KVM_CAP_S390_AIS_MIGRATION,
+    PpcGetCpuChar => KVM_CAP_PPC_GET_CPU_CHAR,
+    S390Bpb => KVM_CAP_S390_BPB,
+    ExceptionPayload => KVM_CAP_EXCEPTION_PAYLOAD,
+    ManualDirtyLogProtect2 => KVM_CAP_MANUAL_DIRTY_LOG_PROTECT2,
+    X86UserSpaceMsr => KVM_CAP_X86_USER_SPACE_MSR,
+    EnforcePvFeatureCpuid => KVM_CAP_ENFORCE_PV_FEATURE_CPUID,
+    DirtyLogRing => KVM_CAP_DIRTY_LOG_RING,
+    HypervEnforceCpuid => KVM_CAP_HYPERV_ENFORCE_CPUID,
+    ExitHypercall => KVM_CAP_EXIT_HYPERCALL,
+    Xsave2 => KVM_CAP_XSAVE2,
+    SysAttributes => KVM_CAP_SYS_ATTRIBUTES,
+}
+
+impl Capability {
+    /// Returns every capability known to libkvm, excluding `Other`.
+    pub fn all() -> &'static [Capability] {
+        ALL_CAPABILITIES
+    }
+}
+
+/// A snapshot of the value KVM reports for every capability in
+/// `Capability::all`, as returned by `KVMSystem::capabilities` and
+/// `VirtualMachine::capabilities`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    values: Vec<(Capability, i32)>,
+}
+
+impl Capabilities {
+    /// Builds a snapshot by calling `check` once for each capability.
+    pub(crate) fn probe<F>(mut check: F) -> Result<Capabilities, Error>
+    where
+        F: FnMut(Capability) -> Result<i32, Error>,
+    {
+        let mut values = Vec::with_capacity(ALL_CAPABILITIES.len());
+        for cap in ALL_CAPABILITIES {
+            values.push((*cap, check(*cap)?));
+        }
+        Ok(Capabilities { values })
+    }
+
+    /// Returns the value reported for a capability, or 0 if the capability
+    /// is not part of the snapshot.
+    pub fn get(&self, cap: Capability) -> i32 {
+        self.values
+            .iter()
+            .find(|&&(c, _)| c == cap)
+            .map(|&(_, value)| value)
+            .unwrap_or(0)
+    }
+
+    /// Returns whether KVM reported a capability as supported.
+    pub fn is_supported(&self, cap: Capability) -> bool {
+        self.get(cap) > 0
+    }
+
+    /// Returns an iterator over every capability and its value.
+    pub fn iter(&self) -> slice::Iter<'_, (Capability, i32)> {
+        self.values.iter()
+    }
+
+    /// Returns the recommended maximum number of virtual CPUs for a VM,
+    /// from `KVM_CAP_NR_VCPUS`. KVM recommends assuming 4 if the capability
+    /// is not supported.
+    pub fn nr_vcpus(&self) -> i32 {
+        match self.get(Capability::NrVcpus) {
+            0 => 4,
+            nr_vcpus => nr_vcpus,
+        }
+    }
+
+    /// Returns the maximum number of virtual CPUs for a VM, from
+    /// `KVM_CAP_MAX_VCPUS`, or `nr_vcpus` if the capability is not
+    /// supported.
+    pub fn max_vcpus(&self) -> i32 {
+        match self.get(Capability::MaxVcpus) {
+            0 => self.nr_vcpus(),
+            max_vcpus => max_vcpus,
+        }
+    }
+
+    /// Returns the maximum number of memory slots for a VM, from
+    /// `KVM_CAP_NR_MEMSLOTS`.
+    pub fn nr_memslots(&self) -> i32 {
+        self.get(Capability::NrMemslots)
+    }
+}
@@ -0,0 +1,1023 @@
+// Copyright (C) 2018, Allison Randal
+//
+// Licensed under LGPL version 2 or any later version.
+
+//! Typed views of x86 virtual CPU state.
+//!
+//! The KVM structures for saving and restoring vCPU state pack several
+//! independent pieces of state together, with flag bits that say which of
+//! them are valid. The types in this module wrap those structures, so the
+//! state can be inspected and changed without tracking the flag bits by
+//! hand.
+
+use std::fmt;
+use std::mem::size_of;
+
+use linux::kvm_bindings::*;
+
+/// An exception that has been queued for delivery to the guest, as reported
+/// by `VcpuEvents::exception`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ExceptionEvent {
+    /// The exception vector, for example 14 for a page fault.
+    pub vector: u8,
+    /// The error code pushed by the exception, if it has one.
+    pub error_code: Option<u32>,
+    /// The exception payload (the faulting address for a page fault, or the
+    /// DR6 bits for a debug exception), if the exception has one. Payloads
+    /// are only reported when `KVM_CAP_EXCEPTION_PAYLOAD` is enabled on the
+    /// VM.
+    pub payload: Option<u64>,
+    /// `true` if delivery of the exception has already started, `false` if
+    /// it is still pending. Pending exceptions are only reported separately
+    /// when `KVM_CAP_EXCEPTION_PAYLOAD` is enabled on the VM; otherwise they
+    /// are reported as injected.
+    pub injected: bool,
+}
+
+/// An external or software interrupt that is being injected into the guest,
+/// as reported by `VcpuEvents::interrupt`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InterruptEvent {
+    /// The interrupt vector.
+    pub vector: u8,
+    /// `true` for a software interrupt raised by an `INT n` instruction.
+    pub soft: bool,
+}
+
+/// The interrupt shadow, which blocks interrupts for one instruction after
+/// `MOV SS`, `POP SS` or `STI`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct InterruptShadow {
+    /// The shadow left by loading SS.
+    pub mov_ss: bool,
+    /// The shadow left by `STI`.
+    pub sti: bool,
+}
+
+/// The system management mode state of a vCPU.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SmmState {
+    /// The vCPU is in SMM.
+    pub smm: bool,
+    /// An SMI is pending.
+    pub pending: bool,
+    /// The vCPU entered SMM while handling an NMI.
+    pub smm_inside_nmi: bool,
+    /// An INIT was received while in SMM, and is held until SMM exits.
+    pub latched_init: bool,
+}
+
+/// Pending and injected events of a vCPU, as returned by
+/// `VirtualCPU::get_vcpu_events`.
+///
+/// The setters also mark the corresponding part of the state as valid, so
+/// `VirtualCPU::set_vcpu_events` only changes the state that was read or
+/// explicitly set.
+///
+/// ```ignore
+/// let mut events = vcpu.get_vcpu_events()?;
+/// events.set_nmi_pending(true);
+/// events.set_interrupt_shadow(InterruptShadow::default());
+/// vcpu.set_vcpu_events(&events)?;
+/// ```
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct VcpuEvents {
+    events: kvm_vcpu_events,
+}
+
+impl VcpuEvents {
+    /// Wraps a raw `kvm_vcpu_events` structure.
+    pub fn from_raw(events: kvm_vcpu_events) -> Self {
+        VcpuEvents { events }
+    }
+
+    /// Returns the raw `kvm_vcpu_events` structure.
+    pub fn as_raw(&self) -> &kvm_vcpu_events {
+        &self.events
+    }
+
+    /// Returns the `KVM_VCPUEVENT_VALID_*` flags, marking which optional
+    /// parts of the state are valid.
+    pub fn flags(&self) -> u32 {
+        self.events.flags
+    }
+
+    /// Returns the exception queued for the guest, if any.
+    pub fn exception(&self) -> Option<ExceptionEvent> {
+        let exception = &self.events.exception;
+        if exception.injected == 0 && exception.pending == 0 {
+            return None;
+        }
+        Some(ExceptionEvent {
+            vector: exception.nr,
+            error_code: if exception.has_error_code != 0 {
+                Some(exception.error_code)
+            } else {
+                None
+            },
+            payload: if self.events.exception_has_payload != 0 {
+                Some(self.events.exception_payload)
+            } else {
+                None
+            },
+            injected: exception.injected != 0,
+        })
+    }
+
+    /// Queues an exception for the guest, or clears the queued exception
+    /// with `None`. Setting a pending exception or a payload requires
+    /// `KVM_CAP_EXCEPTION_PAYLOAD` to be enabled on the VM.
+    pub fn set_exception(&mut self, exception: Option<ExceptionEvent>) {
+        let event = exception.unwrap_or(ExceptionEvent {
+            vector: 0,
+            error_code: None,
+            payload: None,
+            injected: false,
+        });
+        let is_queued = exception.is_some();
+        self.events.exception.nr = event.vector;
+        self.events.exception.injected = (is_queued && event.injected) as u8;
+        self.events.exception.pending = (is_queued && !event.injected) as u8;
+        self.events.exception.has_error_code = event.error_code.is_some() as u8;
+        self.events.exception.error_code = event.error_code.unwrap_or(0);
+        self.events.exception_has_payload = event.payload.is_some() as u8;
+        self.events.exception_payload = event.payload.unwrap_or(0);
+        if self.events.exception.pending != 0 || event.payload.is_some() {
+            self.events.flags |= KVM_VCPUEVENT_VALID_PAYLOAD;
+        }
+    }
+
+    /// Returns the interrupt being injected into the guest, if any.
+    pub fn interrupt(&self) -> Option<InterruptEvent> {
+        let interrupt = &self.events.interrupt;
+        if interrupt.injected == 0 {
+            return None;
+        }
+        Some(InterruptEvent {
+            vector: interrupt.nr,
+            soft: interrupt.soft != 0,
+        })
+    }
+
+    /// Sets the interrupt being injected into the guest, or clears it with
+    /// `None`.
+    pub fn set_interrupt(&mut self, interrupt: Option<InterruptEvent>) {
+        let event = interrupt.unwrap_or(InterruptEvent {
+            vector: 0,
+            soft: false,
+        });
+        self.events.interrupt.injected = interrupt.is_some() as u8;
+        self.events.interrupt.nr = event.vector;
+        self.events.interrupt.soft = event.soft as u8;
+    }
+
+    /// Returns the interrupt shadow.
+    pub fn interrupt_shadow(&self) -> InterruptShadow {
+        let shadow = u32::from(self.events.interrupt.shadow);
+        InterruptShadow {
+            mov_ss: shadow & KVM_X86_SHADOW_INT_MOV_SS != 0,
+            sti: shadow & KVM_X86_SHADOW_INT_STI != 0,
+        }
+    }
+
+    /// Sets the interrupt shadow.
+    pub fn set_interrupt_shadow(&mut self, shadow: InterruptShadow) {
+        let mut bits = 0;
+        if shadow.mov_ss {
+            bits |= KVM_X86_SHADOW_INT_MOV_SS;
+        }
+        if shadow.sti {
+            bits |= KVM_X86_SHADOW_INT_STI;
+        }
+        self.events.interrupt.shadow = bits as u8;
+        self.events.flags |= KVM_VCPUEVENT_VALID_SHADOW;
+    }
+
+    /// Returns `true` if an NMI is being injected into the guest.
+    pub fn nmi_injected(&self) -> bool {
+        self.events.nmi.injected != 0
+    }
+
+    /// Sets whether an NMI is being injected into the guest.
+    pub fn set_nmi_injected(&mut self, injected: bool) {
+        self.events.nmi.injected = injected as u8;
+    }
+
+    /// Returns `true` if an NMI is pending.
+    pub fn nmi_pending(&self) -> bool {
+        self.events.nmi.pending != 0
+    }
+
+    /// Sets whether an NMI is pending.
+    pub fn set_nmi_pending(&mut self, pending: bool) {
+        self.events.nmi.pending = pending as u8;
+        self.events.flags |= KVM_VCPUEVENT_VALID_NMI_PENDING;
+    }
+
+    /// Returns `true` if NMIs are blocked, because the guest is handling an
+    /// NMI.
+    pub fn nmi_masked(&self) -> bool {
+        self.events.nmi.masked != 0
+    }
+
+    /// Sets whether NMIs are blocked.
+    pub fn set_nmi_masked(&mut self, masked: bool) {
+        self.events.nmi.masked = masked as u8;
+    }
+
+    /// Returns the vector of the last SIPI received.
+    pub fn sipi_vector(&self) -> u32 {
+        self.events.sipi_vector
+    }
+
+    /// Sets the SIPI vector, used when an application processor waiting
+    /// for a SIPI is started.
+    pub fn set_sipi_vector(&mut self, vector: u32) {
+        self.events.sipi_vector = vector;
+        self.events.flags |= KVM_VCPUEVENT_VALID_SIPI_VECTOR;
+    }
+
+    /// Returns the system management mode state.
+    pub fn smm(&self) -> SmmState {
+        let smi = &self.events.smi;
+        SmmState {
+            smm: smi.smm != 0,
+            pending: smi.pending != 0,
+            smm_inside_nmi: smi.smm_inside_nmi != 0,
+            latched_init: smi.latched_init != 0,
+        }
+    }
+
+    /// Sets the system management mode state.
+    pub fn set_smm(&mut self, smm: SmmState) {
+        self.events.smi.smm = smm.smm as u8;
+        self.events.smi.pending = smm.pending as u8;
+        self.events.smi.smm_inside_nmi = smm.smm_inside_nmi as u8;
+        self.events.smi.latched_init = smm.latched_init as u8;
+        self.events.flags |= KVM_VCPUEVENT_VALID_SMM;
+    }
+}
+
+/// The multiprocessing state of a vCPU, as returned by
+/// `VirtualCPU::get_mp_state`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MpState {
+    /// The vCPU is running.
+    Runnable,
+    /// An application processor that has not yet received INIT.
+    Uninitialized,
+    /// The vCPU has received INIT, and is waiting for a SIPI.
+    InitReceived,
+    /// The vCPU has executed `HLT`, and is waiting for an interrupt.
+    Halted,
+    /// The vCPU has received a SIPI, and starts at the SIPI vector on the
+    /// next run.
+    SipiReceived,
+    /// An SEV-ES guest vCPU waiting for an AP reset hold to be released.
+    ApResetHold,
+    /// The vCPU is suspended, waiting for a wakeup event.
+    Suspended,
+    /// A state without a name in this library, by `KVM_MP_STATE_*` value.
+    Other(u32),
+}
+
+impl MpState {
+    /// Decodes a `KVM_MP_STATE_*` value.
+    pub fn from_raw(mp_state: u32) -> MpState {
+        match mp_state {
+            KVM_MP_STATE_RUNNABLE => MpState::Runnable,
+            KVM_MP_STATE_UNINITIALIZED => MpState::Uninitialized,
+            KVM_MP_STATE_INIT_RECEIVED => MpState::InitReceived,
+            KVM_MP_STATE_HALTED => MpState::Halted,
+            KVM_MP_STATE_SIPI_RECEIVED => MpState::SipiReceived,
+            KVM_MP_STATE_AP_RESET_HOLD => MpState::ApResetHold,
+            KVM_MP_STATE_SUSPENDED => MpState::Suspended,
+            other => MpState::Other(other),
+        }
+    }
+
+    /// Returns the `KVM_MP_STATE_*` value of the state.
+    pub fn to_raw(&self) -> u32 {
+        match *self {
+            MpState::Runnable => KVM_MP_STATE_RUNNABLE,
+            MpState::Uninitialized => KVM_MP_STATE_UNINITIALIZED,
+            MpState::InitReceived => KVM_MP_STATE_INIT_RECEIVED,
+            MpState::Halted => KVM_MP_STATE_HALTED,
+            MpState::SipiReceived => KVM_MP_STATE_SIPI_RECEIVED,
+            MpState::ApResetHold => KVM_MP_STATE_AP_RESET_HOLD,
+            MpState::Suspended => KVM_MP_STATE_SUSPENDED,
+            MpState::Other(other) => other,
+        }
+    }
+}
+
+/// The number of hardware breakpoints, set in the debug address registers
+/// DR0 to DR3.
+pub const NUM_HW_BREAKPOINTS: usize = 4;
+
+/// The debug status register DR6, reporting the conditions that caused the
+/// last debug exception.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Dr6(u64);
+
+impl Dr6 {
+    const BREAKPOINT_MASK: u64 = 0xf;
+    const BUS_LOCK: u64 = 1 << 11;
+    const DEBUG_REGISTER_ACCESS: u64 = 1 << 13;
+    const SINGLE_STEP: u64 = 1 << 14;
+    const TASK_SWITCH: u64 = 1 << 15;
+    const RTM: u64 = 1 << 16;
+    /// The value of DR6 at reset, with all the status bits clear.
+    pub const RESET: u64 = 0xffff_0ff0;
+
+    /// Creates a DR6 value from its raw bits.
+    pub fn from_bits(bits: u64) -> Dr6 {
+        Dr6(bits)
+    }
+
+    /// Returns the raw bits of the register.
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns true if the breakpoint in DR0 to DR3 selected by `index`
+    /// was hit.
+    ///
+    /// Panics if `index` is not less than `NUM_HW_BREAKPOINTS`.
+    pub fn is_breakpoint_hit(&self, index: usize) -> bool {
+        assert!(index < NUM_HW_BREAKPOINTS);
+        self.0 & (1 << index) != 0
+    }
+
+    /// Returns the indices of all the breakpoints that were hit.
+    pub fn breakpoints_hit(&self) -> Vec<usize> {
+        (0..NUM_HW_BREAKPOINTS)
+            .filter(|&index| self.is_breakpoint_hit(index))
+            .collect()
+    }
+
+    /// Returns true if the exception was caused by a bus lock. Unlike the
+    /// other status bits, this bit is cleared to report the condition.
+    pub fn is_bus_lock(&self) -> bool {
+        self.0 & Dr6::BUS_LOCK == 0
+    }
+
+    /// Returns true if the exception was caused by an access to a debug
+    /// register while general detect was enabled in DR7.
+    pub fn is_debug_register_access(&self) -> bool {
+        self.0 & Dr6::DEBUG_REGISTER_ACCESS != 0
+    }
+
+    /// Returns true if the exception was caused by single-stepping.
+    pub fn is_single_step(&self) -> bool {
+        self.0 & Dr6::SINGLE_STEP != 0
+    }
+
+    /// Returns true if the exception was caused by a task switch to a task
+    /// with the debug trap flag set.
+    pub fn is_task_switch(&self) -> bool {
+        self.0 & Dr6::TASK_SWITCH != 0
+    }
+
+    /// Returns true if the exception occurred inside a transactional
+    /// region. Unlike the other status bits, this bit is cleared to report
+    /// the condition.
+    pub fn is_in_rtm(&self) -> bool {
+        self.0 & Dr6::RTM == 0
+    }
+
+    /// Clears the breakpoint, debug register access, single-step and task
+    /// switch status bits, as a guest debug exception handler would.
+    pub fn clear_status(&mut self) {
+        self.0 &= !(Dr6::BREAKPOINT_MASK
+            | Dr6::DEBUG_REGISTER_ACCESS
+            | Dr6::SINGLE_STEP
+            | Dr6::TASK_SWITCH);
+        self.0 |= Dr6::BUS_LOCK | Dr6::RTM;
+    }
+}
+
+impl Default for Dr6 {
+    fn default() -> Dr6 {
+        Dr6(Dr6::RESET)
+    }
+}
+
+/// The access that triggers a hardware breakpoint, from the R/W fields of
+/// DR7.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BreakpointCondition {
+    /// Break on instruction execution.
+    Execute,
+    /// Break on data writes.
+    Write,
+    /// Break on I/O port reads or writes, when CR4.DE is set.
+    Io,
+    /// Break on data reads or writes, but not instruction fetches.
+    ReadWrite,
+}
+
+/// The size of the memory region watched by a hardware breakpoint, from
+/// the LEN fields of DR7. Execute breakpoints must use `One`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BreakpointLength {
+    /// One byte.
+    One,
+    /// Two bytes, aligned to 2.
+    Two,
+    /// Four bytes, aligned to 4.
+    Four,
+    /// Eight bytes, aligned to 8.
+    Eight,
+}
+
+/// The debug control register DR7, enabling and configuring the hardware
+/// breakpoints in DR0 to DR3.
+///
+/// ```ignore
+/// let mut dr7 = Dr7::default();
+/// dr7.set_breakpoint(0, BreakpointCondition::Write, BreakpointLength::Four);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Dr7(u64);
+
+impl Dr7 {
+    const LOCAL_ENABLE: u64 = 1;
+    const GLOBAL_ENABLE: u64 = 1 << 1;
+    const LOCAL_ENABLE_ALL: u64 = 0x55;
+    const GLOBAL_ENABLE_ALL: u64 = 0xaa;
+    const LOCAL_EXACT: u64 = 1 << 8;
+    const GLOBAL_EXACT: u64 = 1 << 9;
+    const GENERAL_DETECT: u64 = 1 << 13;
+    const CONTROL_SHIFT: usize = 16;
+    const CONTROL_MASK: u64 = 0xf;
+    /// The value of DR7 at reset, with all breakpoints disabled.
+    pub const RESET: u64 = 0x400;
+
+    /// Creates a DR7 value from its raw bits.
+    pub fn from_bits(bits: u64) -> Dr7 {
+        Dr7(bits)
+    }
+
+    /// Returns the raw bits of the register.
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    fn enable_bits(index: usize) -> u64 {
+        assert!(index < NUM_HW_BREAKPOINTS);
+        (Dr7::LOCAL_ENABLE | Dr7::GLOBAL_ENABLE) << (index * 2)
+    }
+
+    fn control_shift(index: usize) -> usize {
+        assert!(index < NUM_HW_BREAKPOINTS);
+        Dr7::CONTROL_SHIFT + index * 4
+    }
+
+    /// Returns true if the breakpoint selected by `index` is enabled,
+    /// either locally or globally.
+    ///
+    /// Panics if `index` is not less than `NUM_HW_BREAKPOINTS`.
+    pub fn is_enabled(&self, index: usize) -> bool {
+        self.0 & Dr7::enable_bits(index) != 0
+    }
+
+    /// Returns true if the breakpoint selected by `index` is enabled for
+    /// the current task only.
+    ///
+    /// Panics if `index` is not less than `NUM_HW_BREAKPOINTS`.
+    pub fn is_local_enabled(&self, index: usize) -> bool {
+        self.0 & Dr7::enable_bits(index) & Dr7::LOCAL_ENABLE_ALL != 0
+    }
+
+    /// Returns true if the breakpoint selected by `index` is enabled for
+    /// all tasks.
+    ///
+    /// Panics if `index` is not less than `NUM_HW_BREAKPOINTS`.
+    pub fn is_global_enabled(&self, index: usize) -> bool {
+        self.0 & Dr7::enable_bits(index) & Dr7::GLOBAL_ENABLE_ALL != 0
+    }
+
+    /// Returns the access that triggers the breakpoint selected by `index`.
+    ///
+    /// Panics if `index` is not less than `NUM_HW_BREAKPOINTS`.
+    pub fn condition(&self, index: usize) -> BreakpointCondition {
+        match (self.0 >> Dr7::control_shift(index)) & 0x3 {
+            0 => BreakpointCondition::Execute,
+            1 => BreakpointCondition::Write,
+            2 => BreakpointCondition::Io,
+            _ => BreakpointCondition::ReadWrite,
+        }
+    }
+
+    /// Returns the size of the region watched by the breakpoint selected
+    /// by `index`.
+    ///
+    /// Panics if `index` is not less than `NUM_HW_BREAKPOINTS`.
+    pub fn length(&self, index: usize) -> BreakpointLength {
+        match (self.0 >> (Dr7::control_shift(index) + 2)) & 0x3 {
+            0 => BreakpointLength::One,
+            1 => BreakpointLength::Two,
+            2 => BreakpointLength::Eight,
+            _ => BreakpointLength::Four,
+        }
+    }
+
+    /// Enables the breakpoint selected by `index` globally, triggering on
+    /// `condition` within `length` bytes of the address in the matching
+    /// debug address register.
+    ///
+    /// Panics if `index` is not less than `NUM_HW_BREAKPOINTS`.
+    pub fn set_breakpoint(
+        &mut self,
+        index: usize,
+        condition: BreakpointCondition,
+        length: BreakpointLength,
+    ) {
+        let condition_bits = match condition {
+            BreakpointCondition::Execute => 0,
+            BreakpointCondition::Write => 1,
+            BreakpointCondition::Io => 2,
+            BreakpointCondition::ReadWrite => 3,
+        };
+        let length_bits = match length {
+            BreakpointLength::One => 0,
+            BreakpointLength::Two => 1,
+            BreakpointLength::Eight => 2,
+            BreakpointLength::Four => 3,
+        };
+        let shift = Dr7::control_shift(index);
+        self.0 &= !(Dr7::CONTROL_MASK << shift);
+        self.0 |= (condition_bits | length_bits << 2) << shift;
+        self.0 |= Dr7::GLOBAL_ENABLE << (index * 2);
+    }
+
+    /// Disables the breakpoint selected by `index`.
+    ///
+    /// Panics if `index` is not less than `NUM_HW_BREAKPOINTS`.
+    pub fn clear_breakpoint(&mut self, index: usize) {
+        self.0 &= !Dr7::enable_bits(index);
+        self.0 &= !(Dr7::CONTROL_MASK << Dr7::control_shift(index));
+    }
+
+    /// Returns true if exact breakpoint matching is enabled, either locally
+    /// or globally.
+    pub fn is_exact(&self) -> bool {
+        self.0 & (Dr7::LOCAL_EXACT | Dr7::GLOBAL_EXACT) != 0
+    }
+
+    /// Returns true if general detect is enabled, raising a debug exception
+    /// on any access to the debug registers.
+    pub fn is_general_detect(&self) -> bool {
+        self.0 & Dr7::GENERAL_DETECT != 0
+    }
+
+    /// Enables or disables general detect.
+    pub fn set_general_detect(&mut self, enabled: bool) {
+        if enabled {
+            self.0 |= Dr7::GENERAL_DETECT;
+        } else {
+            self.0 &= !Dr7::GENERAL_DETECT;
+        }
+    }
+}
+
+impl Default for Dr7 {
+    fn default() -> Dr7 {
+        Dr7(Dr7::RESET)
+    }
+}
+
+/// The debug registers of a vCPU, as returned by
+/// `VirtualCPU::get_debugregs`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct DebugRegs {
+    /// The debug address registers DR0 to DR3.
+    pub db: [u64; NUM_HW_BREAKPOINTS],
+    /// The debug status register.
+    pub dr6: Dr6,
+    /// The debug control register.
+    pub dr7: Dr7,
+}
+
+impl DebugRegs {
+    /// Decodes a raw `kvm_debugregs` structure.
+    pub fn from_raw(regs: &kvm_debugregs) -> Self {
+        DebugRegs {
+            db: regs.db,
+            dr6: Dr6::from_bits(regs.dr6),
+            dr7: Dr7::from_bits(regs.dr7),
+        }
+    }
+
+    /// Returns the raw `kvm_debugregs` structure for the registers.
+    pub fn to_raw(&self) -> kvm_debugregs {
+        kvm_debugregs {
+            db: self.db,
+            dr6: self.dr6.bits(),
+            dr7: self.dr7.bits(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Guest debugging settings for `VirtualCPU::set_guest_debug`, which make
+/// the vCPU exit to userspace with `VcpuExit::Debug` instead of delivering
+/// debug exceptions to the guest.
+///
+/// The hardware breakpoints configured here are separate from the guest's
+/// own debug registers, which KVM preserves while guest debugging is on.
+///
+/// ```ignore
+/// let mut debug = GuestDebug::new();
+/// debug
+///     .single_step(true)
+///     .hardware_breakpoint(0, 0x1000, BreakpointCondition::Execute, BreakpointLength::One);
+/// vcpu.set_guest_debug(&debug)?;
+/// ```
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct GuestDebug {
+    control: u32,
+    debugreg: [u64; 8],
+}
+
+impl GuestDebug {
+    /// Creates settings that enable guest debugging, with no single-step
+    /// or breakpoints yet.
+    pub fn new() -> Self {
+        GuestDebug {
+            control: KVM_GUESTDBG_ENABLE,
+            debugreg: [0, 0, 0, 0, 0, 0, 0, Dr7::RESET],
+        }
+    }
+
+    /// Creates settings that disable guest debugging.
+    pub fn disabled() -> Self {
+        GuestDebug::default()
+    }
+
+    fn set_control(&mut self, flag: u32, enabled: bool) -> &mut Self {
+        if enabled {
+            self.control |= flag;
+        } else {
+            self.control &= !flag;
+        }
+        self
+    }
+
+    /// Enables or disables guest debugging as a whole.
+    pub fn enable(&mut self, enabled: bool) -> &mut Self {
+        self.set_control(KVM_GUESTDBG_ENABLE, enabled)
+    }
+
+    /// Exits after every guest instruction.
+    pub fn single_step(&mut self, enabled: bool) -> &mut Self {
+        self.set_control(KVM_GUESTDBG_SINGLESTEP, enabled)
+    }
+
+    /// Exits when the guest executes `INT3`, so a debugger can patch
+    /// breakpoints into guest code.
+    pub fn software_breakpoints(&mut self, enabled: bool) -> &mut Self {
+        self.set_control(KVM_GUESTDBG_USE_SW_BP, enabled)
+    }
+
+    /// Sets the hardware breakpoint selected by `index` to trigger on
+    /// `condition` within `length` bytes of `address`.
+    ///
+    /// Panics if `index` is not less than `NUM_HW_BREAKPOINTS`.
+    pub fn hardware_breakpoint(
+        &mut self,
+        index: usize,
+        address: u64,
+        condition: BreakpointCondition,
+        length: BreakpointLength,
+    ) -> &mut Self {
+        let mut dr7 = self.dr7();
+        dr7.set_breakpoint(index, condition, length);
+        self.debugreg[index] = address;
+        self.debugreg[7] = dr7.bits();
+        self.set_control(KVM_GUESTDBG_USE_HW_BP, true)
+    }
+
+    /// Clears the hardware breakpoint selected by `index`.
+    ///
+    /// Panics if `index` is not less than `NUM_HW_BREAKPOINTS`.
+    pub fn clear_hardware_breakpoint(&mut self, index: usize) -> &mut Self {
+        let mut dr7 = self.dr7();
+        dr7.clear_breakpoint(index);
+        self.debugreg[index] = 0;
+        self.debugreg[7] = dr7.bits();
+        let in_use = (0..NUM_HW_BREAKPOINTS).any(|index| dr7.is_enabled(index));
+        self.set_control(KVM_GUESTDBG_USE_HW_BP, in_use)
+    }
+
+    /// Returns the addresses of the hardware breakpoints, as loaded into
+    /// DR0 to DR3.
+    pub fn breakpoint_addresses(&self) -> [u64; NUM_HW_BREAKPOINTS] {
+        [
+            self.debugreg[0],
+            self.debugreg[1],
+            self.debugreg[2],
+            self.debugreg[3],
+        ]
+    }
+
+    /// Returns the DR7 value configuring the hardware breakpoints.
+    pub fn dr7(&self) -> Dr7 {
+        Dr7::from_bits(self.debugreg[7])
+    }
+
+    /// Returns the `KVM_GUESTDBG_*` control flags.
+    pub fn control(&self) -> u32 {
+        self.control
+    }
+
+    /// Returns the raw `kvm_guest_debug` structure for the settings.
+    pub fn to_raw(&self) -> kvm_guest_debug {
+        kvm_guest_debug {
+            control: self.control,
+            pad: 0,
+            arch: kvm_guest_debug_arch {
+                debugreg: self.debugreg,
+            },
+        }
+    }
+}
+
+/// A debug exit reported while guest debugging is enabled, as returned in
+/// `VcpuExit::Debug`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DebugExit {
+    /// The exception vector, 1 for a debug exception or 3 for a breakpoint
+    /// instruction.
+    pub exception: u32,
+    /// The guest instruction pointer. For a software breakpoint, this is
+    /// the address of the `INT3` instruction.
+    pub pc: u64,
+    /// The debug status, reporting what caused a debug exception.
+    pub dr6: Dr6,
+    /// The debug control register in effect.
+    pub dr7: Dr7,
+}
+
+impl DebugExit {
+    const DEBUG_VECTOR: u32 = 1;
+    const BREAKPOINT_VECTOR: u32 = 3;
+
+    /// Decodes the raw debug exit information from `kvm_run`.
+    pub fn from_raw(debug: &kvm_debug_exit_arch) -> Self {
+        DebugExit {
+            exception: debug.exception,
+            pc: debug.pc,
+            dr6: Dr6::from_bits(debug.dr6),
+            dr7: Dr7::from_bits(debug.dr7),
+        }
+    }
+
+    /// Returns true if the exit was caused by an `INT3` instruction.
+    pub fn is_software_breakpoint(&self) -> bool {
+        self.exception == DebugExit::BREAKPOINT_VECTOR
+    }
+
+    /// Returns true if the exit was caused by single-stepping.
+    pub fn is_single_step(&self) -> bool {
+        self.exception == DebugExit::DEBUG_VECTOR && self.dr6.is_single_step()
+    }
+
+    /// Returns the indices of the hardware breakpoints that caused the exit.
+    pub fn hardware_breakpoints(&self) -> Vec<usize> {
+        if self.exception != DebugExit::DEBUG_VECTOR {
+            return Vec::new();
+        }
+        self.dr6.breakpoints_hit()
+    }
+}
+
+/// A processor state component saved by `XSAVE`, identified by its bit in
+/// XCR0 and in the XSTATE_BV field of the XSAVE header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum XstateComponent {
+    /// x87 floating point state.
+    X87,
+    /// SSE state, the XMM registers and MXCSR.
+    Sse,
+    /// The upper halves of the YMM registers, for AVX.
+    Avx,
+    /// MPX bound registers.
+    BndRegs,
+    /// MPX bound configuration and status registers.
+    BndCsr,
+    /// AVX-512 opmask registers.
+    Opmask,
+    /// The upper halves of ZMM0 to ZMM15, for AVX-512.
+    ZmmHi256,
+    /// ZMM16 to ZMM31, for AVX-512.
+    Hi16Zmm,
+    /// Processor trace state.
+    Pt,
+    /// The protection key rights register.
+    Pkru,
+    /// PASID state.
+    Pasid,
+    /// User mode control-flow enforcement state.
+    CetUser,
+    /// Supervisor mode control-flow enforcement state.
+    CetSupervisor,
+    /// Hardware duty cycling state.
+    Hdc,
+    /// User interrupt state.
+    Uintr,
+    /// Last branch record state.
+    Lbr,
+    /// Hardware P-state state.
+    Hwp,
+    /// AMX tile configuration.
+    XtileCfg,
+    /// AMX tile data registers.
+    XtileData,
+    /// A component without a name in this library, by bit number.
+    Other(u8),
+}
+
+impl XstateComponent {
+    /// Returns the component for a bit number in XSTATE_BV.
+    pub fn from_bit(bit: u8) -> XstateComponent {
+        match bit {
+            0 => XstateComponent::X87,
+            1 => XstateComponent::Sse,
+            2 => XstateComponent::Avx,
+            3 => XstateComponent::BndRegs,
+            4 => XstateComponent::BndCsr,
+            5 => XstateComponent::Opmask,
+            6 => XstateComponent::ZmmHi256,
+            7 => XstateComponent::Hi16Zmm,
+            8 => XstateComponent::Pt,
+            9 => XstateComponent::Pkru,
+            10 => XstateComponent::Pasid,
+            11 => XstateComponent::CetUser,
+            12 => XstateComponent::CetSupervisor,
+            13 => XstateComponent::Hdc,
+            14 => XstateComponent::Uintr,
+            15 => XstateComponent::Lbr,
+            16 => XstateComponent::Hwp,
+            17 => XstateComponent::XtileCfg,
+            18 => XstateComponent::XtileData,
+            _ => XstateComponent::Other(bit),
+        }
+    }
+
+    /// Returns the bit number of the component in XSTATE_BV.
+    pub fn bit(&self) -> u8 {
+        match *self {
+            XstateComponent::X87 => 0,
+            XstateComponent::Sse => 1,
+            XstateComponent::Avx => 2,
+            XstateComponent::BndRegs => 3,
+            XstateComponent::BndCsr => 4,
+            XstateComponent::Opmask => 5,
+            XstateComponent::ZmmHi256 => 6,
+            XstateComponent::Hi16Zmm => 7,
+            XstateComponent::Pt => 8,
+            XstateComponent::Pkru => 9,
+            XstateComponent::Pasid => 10,
+            XstateComponent::CetUser => 11,
+            XstateComponent::CetSupervisor => 12,
+            XstateComponent::Hdc => 13,
+            XstateComponent::Uintr => 14,
+            XstateComponent::Lbr => 15,
+            XstateComponent::Hwp => 16,
+            XstateComponent::XtileCfg => 17,
+            XstateComponent::XtileData => 18,
+            XstateComponent::Other(bit) => bit,
+        }
+    }
+}
+
+/// A bitmap of XSAVE state components, in the format of XCR0 and the
+/// XSTATE_BV field of the XSAVE header.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct XstateBv(u64);
+
+impl XstateBv {
+    /// Creates a bitmap from its raw bits.
+    pub fn from_bits(bits: u64) -> XstateBv {
+        XstateBv(bits)
+    }
+
+    /// Returns the raw bits of the bitmap.
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns true if the bitmap includes `component`.
+    pub fn contains(&self, component: XstateComponent) -> bool {
+        let bit = component.bit();
+        bit < 64 && self.0 & (1 << bit) != 0
+    }
+
+    /// Returns all the components included in the bitmap, in bit order.
+    pub fn components(&self) -> Vec<XstateComponent> {
+        (0..64)
+            .filter(|&bit| self.0 & (1 << bit) != 0)
+            .map(XstateComponent::from_bit)
+            .collect()
+    }
+}
+
+/// An XSAVE area holding the extended register state of a vCPU, as returned
+/// by `VirtualCPU::get_xsave2`.
+///
+/// The area is at least as large as `kvm_xsave`, and larger when the guest
+/// may use components such as AMX tile data that do not fit in 4096 bytes.
+///
+/// ```ignore
+/// let xsave = vcpu.get_xsave2()?;
+/// if xsave.xstate_bv().contains(XstateComponent::Avx) {
+///     println!("AVX registers are in use");
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Xsave {
+    region: Vec<u32>,
+}
+
+impl Xsave {
+    const XSTATE_BV_OFFSET: usize = 512 / 4;
+    const XCOMP_BV_OFFSET: usize = 520 / 4;
+    const XCOMP_BV_COMPACTED: u64 = 1 << 63;
+
+    /// Creates a zeroed XSAVE area of `size` bytes, rounded up to the size
+    /// of `kvm_xsave` if it is smaller.
+    pub fn with_size(size: usize) -> Self {
+        let size = size.max(size_of::<kvm_xsave>());
+        Xsave {
+            region: vec![0; size.div_ceil(4)],
+        }
+    }
+
+    /// Returns the size of the area in bytes.
+    pub fn size(&self) -> usize {
+        self.region.len() * 4
+    }
+
+    /// Returns the contents of the area.
+    pub fn region(&self) -> &[u32] {
+        &self.region
+    }
+
+    /// Returns the contents of the area for modification.
+    pub fn region_mut(&mut self) -> &mut [u32] {
+        &mut self.region
+    }
+
+    fn read_u64(&self, offset: usize) -> u64 {
+        u64::from(self.region[offset]) | u64::from(self.region[offset + 1]) << 32
+    }
+
+    /// Returns the XSTATE_BV field of the XSAVE header, listing the
+    /// components that hold state other than their initial values.
+    pub fn xstate_bv(&self) -> XstateBv {
+        XstateBv::from_bits(self.read_u64(Xsave::XSTATE_BV_OFFSET))
+    }
+
+    /// Returns the XCOMP_BV field of the XSAVE header, listing the
+    /// components present when the area is in compacted format.
+    pub fn xcomp_bv(&self) -> XstateBv {
+        XstateBv::from_bits(self.read_u64(Xsave::XCOMP_BV_OFFSET) & !Xsave::XCOMP_BV_COMPACTED)
+    }
+
+    /// Returns true if the area is in the compacted format.
+    pub fn is_compacted(&self) -> bool {
+        self.read_u64(Xsave::XCOMP_BV_OFFSET) & Xsave::XCOMP_BV_COMPACTED != 0
+    }
+
+    pub(crate) fn as_ptr(&self) -> *const u32 {
+        self.region.as_ptr()
+    }
+
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut u32 {
+        self.region.as_mut_ptr()
+    }
+}
+
+// The area can be many kilobytes long, so only the size and header are
+// printed.
+impl fmt::Debug for Xsave {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Xsave")
+            .field("size", &self.size())
+            .field("xstate_bv", &self.xstate_bv())
+            .field("xcomp_bv", &self.xcomp_bv())
+            .field("is_compacted", &self.is_compacted())
+            .finish()
+    }
+}
+
+impl From<&kvm_xsave> for Xsave {
+    fn from(xsave: &kvm_xsave) -> Self {
+        Xsave {
+            region: xsave.region.to_vec(),
+        }
+    }
+}
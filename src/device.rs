@@ -0,0 +1,302 @@
+// Copyright (C) 2018, Allison Randal
+//
+// Licensed under LGPL version 2 or any later version.
+
+//! In-kernel devices and device attributes.
+//!
+//! The device control API creates emulated devices inside KVM with
+//! `KVM_CREATE_DEVICE`, and each device gets its own filehandle. Devices
+//! are configured through attributes, which are identified by a group and
+//! an attribute number specific to the device type. The same attribute
+//! operations are also available on virtual CPUs and on the KVM system
+//! filehandle, where they report or change settings that have no ioctl of
+//! their own.
+
+use libc;
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use linux::kvm_bindings::*;
+use linux::kvm_ioctl::{KVM_GET_DEVICE_ATTR, KVM_HAS_DEVICE_ATTR, KVM_SET_DEVICE_ATTR};
+
+/// The type of an in-kernel device created by
+/// `VirtualMachine::create_device`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeviceType {
+    /// A Freescale MPIC 2.0 interrupt controller (PowerPC).
+    FslMpic20,
+    /// A Freescale MPIC 4.2 interrupt controller (PowerPC).
+    FslMpic42,
+    /// A XICS interrupt controller (PowerPC).
+    Xics,
+    /// The VFIO pseudo device, which tells KVM about VFIO groups assigned
+    /// to the guest.
+    Vfio,
+    /// An ARM GICv2 interrupt controller.
+    ArmVgicV2,
+    /// A floating interrupt controller (s390).
+    Flic,
+    /// An ARM GICv3 interrupt controller.
+    ArmVgicV3,
+    /// An ARM GICv3 interrupt translation service.
+    ArmVgicIts,
+    /// A device type that is not listed here, by its `KVM_DEV_TYPE_*`
+    /// number.
+    Other(u32),
+}
+
+impl DeviceType {
+    /// Converts one of the `KVM_DEV_TYPE_*` numbers to a `DeviceType`.
+    #[allow(non_upper_case_globals)]
+    pub fn from_raw(device_type: u32) -> DeviceType {
+        match device_type {
+            kvm_device_type_KVM_DEV_TYPE_FSL_MPIC_20 => DeviceType::FslMpic20,
+            kvm_device_type_KVM_DEV_TYPE_FSL_MPIC_42 => DeviceType::FslMpic42,
+            kvm_device_type_KVM_DEV_TYPE_XICS => DeviceType::Xics,
+            kvm_device_type_KVM_DEV_TYPE_VFIO => DeviceType::Vfio,
+            kvm_device_type_KVM_DEV_TYPE_ARM_VGIC_V2 => DeviceType::ArmVgicV2,
+            kvm_device_type_KVM_DEV_TYPE_FLIC => DeviceType::Flic,
+            kvm_device_type_KVM_DEV_TYPE_ARM_VGIC_V3 => DeviceType::ArmVgicV3,
+            kvm_device_type_KVM_DEV_TYPE_ARM_VGIC_ITS => DeviceType::ArmVgicIts,
+            other => DeviceType::Other(other),
+        }
+    }
+
+    /// Returns the `KVM_DEV_TYPE_*` number of the device type.
+    pub fn to_raw(&self) -> u32 {
+        match *self {
+            DeviceType::FslMpic20 => kvm_device_type_KVM_DEV_TYPE_FSL_MPIC_20,
+            DeviceType::FslMpic42 => kvm_device_type_KVM_DEV_TYPE_FSL_MPIC_42,
+            DeviceType::Xics => kvm_device_type_KVM_DEV_TYPE_XICS,
+            DeviceType::Vfio => kvm_device_type_KVM_DEV_TYPE_VFIO,
+            DeviceType::ArmVgicV2 => kvm_device_type_KVM_DEV_TYPE_ARM_VGIC_V2,
+            DeviceType::Flic => kvm_device_type_KVM_DEV_TYPE_FLIC,
+            DeviceType::ArmVgicV3 => kvm_device_type_KVM_DEV_TYPE_ARM_VGIC_V3,
+            DeviceType::ArmVgicIts => kvm_device_type_KVM_DEV_TYPE_ARM_VGIC_ITS,
+            DeviceType::Other(other) => other,
+        }
+    }
+}
+
+/// An in-kernel device, as returned by `VirtualMachine::create_device`. It
+/// owns the filehandle for device operations. The device itself belongs to
+/// the VM, and is only destroyed along with the VM.
+#[derive(Debug)]
+pub struct Device {
+    ioctl: File,
+    device_type: DeviceType,
+}
+
+impl Device {
+    /// Creates a new `Device` from an existing filehandle for device
+    /// operations.
+    pub fn from_file(handle: File, device_type: DeviceType) -> Self {
+        Device {
+            ioctl: handle,
+            device_type,
+        }
+    }
+
+    /// Returns the type of the device.
+    pub fn device_type(&self) -> DeviceType {
+        self.device_type
+    }
+
+    /// Checks whether the device supports an attribute.
+    ///
+    ///     # use libkvm::device::*;
+    ///     # use libkvm::linux::kvm_bindings::*;
+    ///     # use libkvm::system::*;
+    ///     # let system = KVMSystem::new().expect("failed to connect to KVM");
+    ///     # let vm = system.create_vm().expect("failed to create VM");
+    ///     let vfio = vm.create_device(DeviceType::Vfio).expect("failed to create device");
+    ///     let result = vfio.has_attr(KVM_DEV_VFIO_GROUP, KVM_DEV_VFIO_GROUP_ADD as u64);
+
+    pub fn has_attr(&self, group: u32, attr: u64) -> Result<bool, Error> {
+        has_attr(self.ioctl.as_raw_fd(), group, attr)
+    }
+
+    /// Reads the value of a device attribute that holds at most 64 bits,
+    /// such as the number of interrupts of an ARM GIC. Fails with
+    /// `ErrorKind::InvalidInput` for other attributes, which can be read
+    /// with `get_attr_raw`.
+    pub fn get_attr(&self, group: u32, attr: u64) -> Result<u64, Error> {
+        get_attr(self.ioctl.as_raw_fd(), group, attr, self.u64_attrs())
+    }
+
+    /// Reads the value of a device attribute into the buffer at `addr`.
+    ///
+    /// # Safety
+    ///
+    /// KVM writes as many bytes to `addr` as the attribute holds, which
+    /// depends on the device type and the attribute, and can be large (for
+    /// example, all pending interrupts of a `Flic`). The caller must
+    /// guarantee that `addr` points to a writable buffer large enough for
+    /// the attribute.
+    pub unsafe fn get_attr_raw(&self, group: u32, attr: u64, addr: u64) -> Result<(), Error> {
+        get_attr_raw(self.ioctl.as_raw_fd(), group, attr, addr)
+    }
+
+    /// Changes the value of a device attribute that holds at most 64 bits,
+    /// such as a VFIO group file descriptor. Attributes that take a 32-bit
+    /// value only use the low 32 bits of `value`. Fails with
+    /// `ErrorKind::InvalidInput` for other attributes, which can be set
+    /// with `set_attr_raw`.
+    pub fn set_attr(&self, group: u32, attr: u64, value: u64) -> Result<(), Error> {
+        set_attr(self.ioctl.as_raw_fd(), group, attr, value, self.u64_attrs())
+    }
+
+    /// Changes the value of a device attribute to the contents of the
+    /// buffer at `addr`.
+    ///
+    /// # Safety
+    ///
+    /// KVM reads as many bytes from `addr` as the attribute holds, and
+    /// some attributes contain pointers that KVM follows. The caller must
+    /// guarantee that `addr` points to a valid value for the attribute.
+    pub unsafe fn set_attr_raw(&self, group: u32, attr: u64, addr: u64) -> Result<(), Error> {
+        set_attr_raw(self.ioctl.as_raw_fd(), group, attr, addr)
+    }
+
+    fn u64_attrs(&self) -> &'static [(u32, u64)] {
+        match self.device_type {
+            DeviceType::Vfio => VFIO_U64_ATTRS,
+            DeviceType::ArmVgicV2 | DeviceType::ArmVgicV3 => ARM_VGIC_U64_ATTRS,
+            _ => &[],
+        }
+    }
+}
+
+impl AsRawFd for Device {
+    fn as_raw_fd(&self) -> RawFd {
+        self.ioctl.as_raw_fd()
+    }
+}
+
+// The attribute operations below are shared by devices, virtual CPUs and
+// the KVM system filehandle. KVM reports attributes that a filehandle does
+// not know about with ENXIO.
+
+// The ARM GIC attribute groups, which are not in the x86 bindings.
+const KVM_DEV_ARM_VGIC_GRP_NR_IRQS: u32 = 3;
+const KVM_DEV_ARM_VGIC_GRP_CTRL: u32 = 4;
+const KVM_DEV_ARM_VGIC_CTRL_INIT: u64 = 0;
+
+// The attributes that hold at most 64 bits, by device type, which
+// `get_attr` and `set_attr` can pass through a u64.
+const VFIO_U64_ATTRS: &[(u32, u64)] = &[
+    (KVM_DEV_VFIO_GROUP, KVM_DEV_VFIO_GROUP_ADD as u64),
+    (KVM_DEV_VFIO_GROUP, KVM_DEV_VFIO_GROUP_DEL as u64),
+    (KVM_DEV_VFIO_GROUP, KVM_DEV_VFIO_GROUP_SET_SPAPR_TCE as u64),
+];
+
+const ARM_VGIC_U64_ATTRS: &[(u32, u64)] = &[
+    (KVM_DEV_ARM_VGIC_GRP_NR_IRQS, 0),
+    (KVM_DEV_ARM_VGIC_GRP_CTRL, KVM_DEV_ARM_VGIC_CTRL_INIT),
+];
+
+// The vCPU attributes that hold at most 64 bits.
+pub(crate) const VCPU_U64_ATTRS: &[(u32, u64)] = &[(KVM_VCPU_TSC_CTRL, KVM_VCPU_TSC_OFFSET as u64)];
+
+// The system attributes that hold at most 64 bits.
+pub(crate) const SYSTEM_U64_ATTRS: &[(u32, u64)] =
+    &[(KVM_X86_GRP_SYSTEM, KVM_X86_XCOMP_GUEST_SUPP as u64)];
+
+pub(crate) fn has_attr(fd: RawFd, group: u32, attr: u64) -> Result<bool, Error> {
+    let device_attr = kvm_device_attr {
+        group,
+        attr,
+        ..Default::default()
+    };
+    let result = unsafe { libc::ioctl(fd, KVM_HAS_DEVICE_ATTR, &device_attr) };
+    if result == 0 {
+        return Ok(true);
+    }
+    let error = Error::last_os_error();
+    if error.raw_os_error() == Some(libc::ENXIO) {
+        return Ok(false);
+    } else {
+        return Err(error);
+    }
+}
+
+fn check_u64_attr(group: u32, attr: u64, known: &[(u32, u64)]) -> Result<(), Error> {
+    if known.contains(&(group, attr)) {
+        return Ok(());
+    } else {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "attribute is not known to fit in 64 bits",
+        ));
+    }
+}
+
+// Reads an attribute through a u64, if it is one of the `known` attributes.
+// KVM writes the whole attribute to the buffer, so any other attribute
+// could overrun it.
+pub(crate) fn get_attr(
+    fd: RawFd,
+    group: u32,
+    attr: u64,
+    known: &[(u32, u64)],
+) -> Result<u64, Error> {
+    check_u64_attr(group, attr, known)?;
+    let mut value: u64 = 0;
+    unsafe { get_attr_raw(fd, group, attr, &mut value as *mut u64 as u64)? };
+    Ok(value)
+}
+
+pub(crate) unsafe fn get_attr_raw(
+    fd: RawFd,
+    group: u32,
+    attr: u64,
+    addr: u64,
+) -> Result<(), Error> {
+    let device_attr = kvm_device_attr {
+        group,
+        attr,
+        addr,
+        ..Default::default()
+    };
+    let result = libc::ioctl(fd, KVM_GET_DEVICE_ATTR, &device_attr);
+    if result == 0 {
+        return Ok(());
+    } else {
+        return Err(Error::last_os_error());
+    }
+}
+
+// Writes an attribute from a u64, if it is one of the `known` attributes.
+// KVM reads the whole attribute from the buffer, so any other attribute
+// could read past it.
+pub(crate) fn set_attr(
+    fd: RawFd,
+    group: u32,
+    attr: u64,
+    value: u64,
+    known: &[(u32, u64)],
+) -> Result<(), Error> {
+    check_u64_attr(group, attr, known)?;
+    unsafe { set_attr_raw(fd, group, attr, &value as *const u64 as u64) }
+}
+
+pub(crate) unsafe fn set_attr_raw(
+    fd: RawFd,
+    group: u32,
+    attr: u64,
+    addr: u64,
+) -> Result<(), Error> {
+    let device_attr = kvm_device_attr {
+        group,
+        attr,
+        addr,
+        ..Default::default()
+    };
+    let result = libc::ioctl(fd, KVM_SET_DEVICE_ATTR, &device_attr);
+    if result == 0 {
+        return Ok(());
+    } else {
+        return Err(Error::last_os_error());
+    }
+}
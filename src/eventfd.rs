@@ -0,0 +1,86 @@
+// Copyright (C) 2018, Allison Randal
+//
+// Licensed under LGPL version 2 or any later version.
+
+//! Event notification filehandles.
+//!
+//! Several KVM operations, such as ioeventfd and irqfd, signal or wait on a
+//! Linux `eventfd` instead of exiting to userspace. The `EventFd` struct
+//! owns one of these filehandles, so it can be shared between the VM and a
+//! device thread without exposing raw file descriptors.
+
+extern crate libc;
+
+use std::fs::File;
+use std::io::{Error, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+/// An owned `eventfd` filehandle, holding a 64 bit counter.
+#[derive(Debug)]
+pub struct EventFd {
+    eventfd: File,
+}
+
+impl EventFd {
+    /// Creates a new eventfd with a counter of 0, and returns a `Result`.
+    /// If the `nonblocking` flag is set, `read` returns a `WouldBlock`
+    /// error instead of waiting when the counter is 0.
+    ///
+    ///     # use libkvm::eventfd::*;
+    ///     let eventfd = EventFd::new(false).expect("failed to create eventfd");
+
+    pub fn new(nonblocking: bool) -> Result<EventFd, Error> {
+        let mut flags = libc::EFD_CLOEXEC;
+        if nonblocking {
+            flags |= libc::EFD_NONBLOCK;
+        }
+        let raw_fd = unsafe { libc::eventfd(0, flags) };
+        if raw_fd < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        // Return value is safe because raw file descriptor result is checked
+        // and ownership of File struct is consumed by EventFd struct.
+        Ok(EventFd {
+            eventfd: unsafe { File::from_raw_fd(raw_fd) },
+        })
+    }
+
+    /// Adds `value` to the counter, waking up any reader.
+    ///
+    ///     # use libkvm::eventfd::*;
+    ///     # let eventfd = EventFd::new(false).expect("failed to create eventfd");
+    ///     eventfd.write(1).expect("failed to signal eventfd");
+
+    pub fn write(&self, value: u64) -> Result<(), Error> {
+        (&self.eventfd).write_all(&value.to_ne_bytes())
+    }
+
+    /// Returns the value of the counter and resets it to 0, waiting until
+    /// the counter is non-zero unless the eventfd is non-blocking.
+    ///
+    ///     # use libkvm::eventfd::*;
+    ///     # let eventfd = EventFd::new(false).expect("failed to create eventfd");
+    ///     eventfd.write(2).expect("failed to signal eventfd");
+    ///     assert_eq!(eventfd.read().expect("failed to read eventfd"), 2);
+
+    pub fn read(&self) -> Result<u64, Error> {
+        let mut buf = [0; 8];
+        (&self.eventfd).read_exact(&mut buf)?;
+        Ok(u64::from_ne_bytes(buf))
+    }
+
+    /// Creates a new `EventFd` that refers to the same counter, for passing
+    /// to another thread.
+    pub fn try_clone(&self) -> Result<EventFd, Error> {
+        Ok(EventFd {
+            eventfd: self.eventfd.try_clone()?,
+        })
+    }
+}
+
+impl AsRawFd for EventFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.eventfd.as_raw_fd()
+    }
+}
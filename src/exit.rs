@@ -0,0 +1,468 @@
+// Copyright (C) 2018, Allison Randal
+//
+// Licensed under LGPL version 2 or any later version.
+
+//! Decoded exit reasons for the virtual CPU `run` operation.
+//!
+//! When `KVM_RUN` returns, KVM describes why the guest stopped in the
+//! `exit_reason` field of the shared `kvm_run` structure, with the details
+//! stored in a union that is only valid for that particular exit reason.
+//! The types in this module decode that union once, so a hypervisor's run
+//! loop can match on a plain Rust enum without any unsafe code. Payload
+//! fields that userspace is expected to fill in before the next `run`
+//! (such as hypercall return values) are handed out as mutable borrows of
+//! the shared structure.
+
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::marker::PhantomData;
+use std::slice::{self, Chunks, ChunksMut};
+
+use cpu::DebugExit;
+use linux::kvm_bindings::*;
+
+/// The reason a virtual CPU exited to userspace, as returned by
+/// `VirtualCPU::run_exit`.
+///
+/// Exit reasons that are only produced on other architectures (s390 and
+/// PowerPC) are included for completeness, but carry no payload.
+#[derive(Debug)]
+pub enum VcpuExit<'a> {
+    /// The exit reason was not recognised by KVM. The hardware specific
+    /// exit reason is included.
+    Unknown { hardware_exit_reason: u64 },
+    /// The guest raised an exception that KVM could not handle.
+    Exception { exception: u32, error_code: u32 },
+    /// The guest executed a port I/O instruction.
+    Io(IoExit<'a>),
+    /// The guest made a hypercall. Write the result into `ret` before the
+    /// next `run`.
+    Hypercall {
+        nr: u64,
+        args: [u64; 6],
+        longmode: bool,
+        ret: &'a mut u64,
+    },
+    /// A debug exception was raised while guest debugging was enabled.
+    Debug(DebugExit),
+    /// The guest executed a `hlt` instruction.
+    Hlt,
+    /// The guest accessed memory that is not backed by a memory slot.
+    Mmio(MmioExit<'a>),
+    /// The guest is ready to accept an interrupt, as requested by
+    /// `request_interrupt_window`.
+    IrqWindowOpen,
+    /// The guest triggered a triple fault.
+    Shutdown,
+    /// KVM failed to enter the guest. The hardware specific failure reason
+    /// is included.
+    FailEntry { hardware_entry_failure_reason: u64 },
+    /// The run was interrupted by a signal or by `VcpuHandle::kick`
+    /// before the guest exited.
+    Interrupted,
+    /// The guest wrote the task priority register.
+    SetTpr,
+    /// The guest accessed the task priority register.
+    TprAccess { rip: u64, is_write: bool },
+    /// s390 only.
+    S390Sieic,
+    /// s390 only.
+    S390Reset,
+    /// PowerPC only.
+    Dcr,
+    /// A non-maskable interrupt was delivered.
+    Nmi,
+    /// KVM hit an internal error, such as an instruction it could not
+    /// emulate. The `suberror` is one of the `KVM_INTERNAL_ERROR_*`
+    /// constants, and `data` holds any extra diagnostic words.
+    InternalError { suberror: u32, data: &'a [u64] },
+    /// PowerPC only.
+    Osi,
+    /// PowerPC only.
+    PaprHcall,
+    /// s390 only.
+    S390Ucontrol,
+    /// The guest watchdog fired.
+    Watchdog,
+    /// s390 only.
+    S390Tsch,
+    /// PowerPC only.
+    Epr,
+    /// The guest requested a system level event. The `event_type` is one
+    /// of the `KVM_SYSTEM_EVENT_*` constants.
+    SystemEvent { event_type: u32, flags: u64 },
+    /// s390 only.
+    S390Stsi,
+    /// The guest signalled end of interrupt for a level triggered IOAPIC
+    /// interrupt routed to userspace.
+    IoapicEoi { vector: u8 },
+    /// The guest changed its Hyper-V SynIC state.
+    HypervSynic {
+        msr: u32,
+        control: u64,
+        evt_page: u64,
+        msg_page: u64,
+    },
+    /// The guest made a Hyper-V hypercall. Write the result into `result`
+    /// before the next `run`.
+    HypervHcall {
+        input: u64,
+        params: [u64; 2],
+        result: &'a mut u64,
+    },
+    /// The guest read an MSR that is handled in userspace, as enabled with
+    /// `VmCap::UserSpaceMsr`. `reason` is one of the
+    /// `KVM_MSR_EXIT_REASON_*` flags. Write the value into `data`, or set
+    /// `error` to 1 to inject a #GP into the guest, before the next `run`.
+    X86Rdmsr {
+        reason: u32,
+        index: u32,
+        data: &'a mut u64,
+        error: &'a mut u8,
+    },
+    /// The guest wrote an MSR that is handled in userspace, as enabled with
+    /// `VmCap::UserSpaceMsr`. `reason` is one of the
+    /// `KVM_MSR_EXIT_REASON_*` flags. Set `error` to 1 to inject a #GP
+    /// into the guest before the next `run`.
+    X86Wrmsr {
+        reason: u32,
+        index: u32,
+        data: u64,
+        error: &'a mut u8,
+    },
+    /// The dirty ring of the virtual CPU is full. Harvest the ring and
+    /// reset it with `VirtualMachine::reset_dirty_rings` before the next
+    /// `run`.
+    DirtyRingFull,
+    /// An exit reason that this version of libKVM does not know about.
+    Unsupported(u32),
+}
+
+impl<'a> VcpuExit<'a> {
+    /// Decodes the exit reason stored in a `kvm_run` mapping.
+    ///
+    /// The caller must guarantee that `kvm_run` points to a live mapping of
+    /// `map_size` bytes, and that nothing else accesses the mapping for the
+    /// lifetime `'a`.
+    pub(crate) unsafe fn from_raw(kvm_run: *mut kvm_run, map_size: usize) -> VcpuExit<'a> {
+        // Only the exit data is borrowed, as `immediate_exit` may be written
+        // by a `VcpuHandle` on another thread.
+        let exit_reason = (*kvm_run).exit_reason;
+        let exit = &mut (*kvm_run).__bindgen_anon_1;
+        match exit_reason {
+            KVM_EXIT_UNKNOWN => VcpuExit::Unknown {
+                hardware_exit_reason: exit.hw.hardware_exit_reason,
+            },
+            KVM_EXIT_EXCEPTION => VcpuExit::Exception {
+                exception: exit.ex.exception,
+                error_code: exit.ex.error_code,
+            },
+            KVM_EXIT_IO => VcpuExit::Io(IoExit::from_raw(kvm_run, map_size)),
+            KVM_EXIT_HYPERCALL => VcpuExit::Hypercall {
+                nr: exit.hypercall.nr,
+                args: exit.hypercall.args,
+                longmode: exit.hypercall.longmode != 0,
+                ret: &mut exit.hypercall.ret,
+            },
+            KVM_EXIT_DEBUG => VcpuExit::Debug(DebugExit::from_raw(&exit.debug.arch)),
+            KVM_EXIT_HLT => VcpuExit::Hlt,
+            KVM_EXIT_MMIO => VcpuExit::Mmio(MmioExit::from_raw(kvm_run)),
+            KVM_EXIT_IRQ_WINDOW_OPEN => VcpuExit::IrqWindowOpen,
+            KVM_EXIT_SHUTDOWN => VcpuExit::Shutdown,
+            KVM_EXIT_FAIL_ENTRY => VcpuExit::FailEntry {
+                hardware_entry_failure_reason: exit.fail_entry.hardware_entry_failure_reason,
+            },
+            KVM_EXIT_INTR => VcpuExit::Interrupted,
+            KVM_EXIT_SET_TPR => VcpuExit::SetTpr,
+            KVM_EXIT_TPR_ACCESS => VcpuExit::TprAccess {
+                rip: exit.tpr_access.rip,
+                is_write: exit.tpr_access.is_write != 0,
+            },
+            KVM_EXIT_S390_SIEIC => VcpuExit::S390Sieic,
+            KVM_EXIT_S390_RESET => VcpuExit::S390Reset,
+            KVM_EXIT_DCR => VcpuExit::Dcr,
+            KVM_EXIT_NMI => VcpuExit::Nmi,
+            KVM_EXIT_INTERNAL_ERROR => {
+                let internal = &exit.internal;
+                let ndata = (internal.ndata as usize).min(internal.data.len());
+                VcpuExit::InternalError {
+                    suberror: internal.suberror,
+                    data: &internal.data[..ndata],
+                }
+            }
+            KVM_EXIT_OSI => VcpuExit::Osi,
+            KVM_EXIT_PAPR_HCALL => VcpuExit::PaprHcall,
+            KVM_EXIT_S390_UCONTROL => VcpuExit::S390Ucontrol,
+            KVM_EXIT_WATCHDOG => VcpuExit::Watchdog,
+            KVM_EXIT_S390_TSCH => VcpuExit::S390Tsch,
+            KVM_EXIT_EPR => VcpuExit::Epr,
+            KVM_EXIT_SYSTEM_EVENT => VcpuExit::SystemEvent {
+                event_type: exit.system_event.type_,
+                flags: exit.system_event.flags,
+            },
+            KVM_EXIT_S390_STSI => VcpuExit::S390Stsi,
+            KVM_EXIT_IOAPIC_EOI => VcpuExit::IoapicEoi {
+                vector: exit.eoi.vector,
+            },
+            KVM_EXIT_HYPERV => match exit.hyperv.type_ {
+                KVM_EXIT_HYPERV_SYNIC => {
+                    let synic = exit.hyperv.u.synic;
+                    VcpuExit::HypervSynic {
+                        msr: synic.msr,
+                        control: synic.control,
+                        evt_page: synic.evt_page,
+                        msg_page: synic.msg_page,
+                    }
+                }
+                KVM_EXIT_HYPERV_HCALL => {
+                    let hcall = &mut exit.hyperv.u.hcall;
+                    VcpuExit::HypervHcall {
+                        input: hcall.input,
+                        params: hcall.params,
+                        result: &mut hcall.result,
+                    }
+                }
+                _ => VcpuExit::Unsupported(exit_reason),
+            },
+            KVM_EXIT_X86_RDMSR => {
+                let msr = &mut exit.msr;
+                VcpuExit::X86Rdmsr {
+                    reason: msr.reason,
+                    index: msr.index,
+                    data: &mut msr.data,
+                    error: &mut msr.error,
+                }
+            }
+            KVM_EXIT_X86_WRMSR => {
+                let msr = &mut exit.msr;
+                VcpuExit::X86Wrmsr {
+                    reason: msr.reason,
+                    index: msr.index,
+                    data: msr.data,
+                    error: &mut msr.error,
+                }
+            }
+            KVM_EXIT_DIRTY_RING_FULL => VcpuExit::DirtyRingFull,
+            reason => VcpuExit::Unsupported(reason),
+        }
+    }
+}
+
+/// The direction of a port I/O access.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IoDirection {
+    /// The guest is reading from the port, and expects userspace to supply
+    /// the data.
+    In,
+    /// The guest is writing to the port.
+    Out,
+}
+
+/// A port I/O exit, borrowed from the `kvm_run` mapping of a virtual CPU.
+///
+/// KVM places the data for the access in the `kvm_run` mapping, at
+/// `data_offset` bytes from the start. For string instructions with a `rep`
+/// prefix, the data for all `count` iterations is stored contiguously, so
+/// the data buffer is `count * size` bytes long. For `In` accesses, the
+/// handler must fill in the buffer before the next `run`.
+pub struct IoExit<'a> {
+    kvm_run: *mut kvm_run,
+    map_size: usize,
+    _run: PhantomData<&'a mut kvm_run>,
+}
+
+impl<'a> IoExit<'a> {
+    /// The caller must guarantee the same conditions as for
+    /// `VcpuExit::from_raw`, and that the last exit was `KVM_EXIT_IO`.
+    pub(crate) unsafe fn from_raw(kvm_run: *mut kvm_run, map_size: usize) -> IoExit<'a> {
+        IoExit {
+            kvm_run,
+            map_size,
+            _run: PhantomData,
+        }
+    }
+
+    fn io(&self) -> &kvm_run__bindgen_ty_1__bindgen_ty_4 {
+        unsafe { &(*self.kvm_run).__bindgen_anon_1.io }
+    }
+
+    /// Returns whether the guest is reading from or writing to the port.
+    pub fn direction(&self) -> IoDirection {
+        if self.io().direction == KVM_EXIT_IO_OUT as u8 {
+            IoDirection::Out
+        } else {
+            IoDirection::In
+        }
+    }
+
+    /// Returns the port number accessed by the guest.
+    pub fn port(&self) -> u16 {
+        self.io().port
+    }
+
+    /// Returns the size of a single access, in bytes (1, 2 or 4).
+    pub fn size(&self) -> usize {
+        self.io().size as usize
+    }
+
+    /// Returns the number of repeated accesses, which is greater than one
+    /// for string instructions with a `rep` prefix.
+    pub fn count(&self) -> usize {
+        self.io().count as usize
+    }
+
+    // Returns a pointer to the data buffer and its length in bytes. The
+    // offset reported by KVM is checked against the size of the mapping, so
+    // a bogus offset results in an empty buffer rather than an out of
+    // bounds access.
+    fn data_raw(&self) -> (*mut u8, usize) {
+        let offset = self.io().data_offset as usize;
+        let len = self.size() * self.count();
+        match offset.checked_add(len) {
+            Some(end) if end <= self.map_size => {
+                (unsafe { (self.kvm_run as *mut u8).add(offset) }, len)
+            }
+            _ => (self.kvm_run as *mut u8, 0),
+        }
+    }
+
+    /// Returns the data for all iterations of the access, `count * size`
+    /// bytes long.
+    pub fn data(&self) -> &[u8] {
+        let (data, len) = self.data_raw();
+        unsafe { slice::from_raw_parts(data, len) }
+    }
+
+    /// Returns the data for all iterations of the access, `count * size`
+    /// bytes long, for writing the response to an `In` access.
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        let (data, len) = self.data_raw();
+        unsafe { slice::from_raw_parts_mut(data, len) }
+    }
+
+    /// Returns an iterator over the data for each iteration of the access,
+    /// in order, each `size` bytes long.
+    ///
+    /// ```ignore
+    /// for byte in io.iterations() {
+    ///     serial.write(byte[0]);
+    /// }
+    /// ```
+    pub fn iterations(&self) -> Chunks<'_, u8> {
+        let size = self.size().max(1);
+        self.data().chunks(size)
+    }
+
+    /// Returns an iterator over the data for each iteration of the access,
+    /// in order, each `size` bytes long, for writing the response to an `In`
+    /// access.
+    pub fn iterations_mut(&mut self) -> ChunksMut<'_, u8> {
+        let size = self.size().max(1);
+        self.data_mut().chunks_mut(size)
+    }
+}
+
+impl<'a> fmt::Debug for IoExit<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IoExit")
+            .field("direction", &self.direction())
+            .field("port", &self.port())
+            .field("size", &self.size())
+            .field("count", &self.count())
+            .finish()
+    }
+}
+
+/// A memory mapped I/O exit, borrowed from the `kvm_run` mapping of a
+/// virtual CPU.
+///
+/// For writes, the data written by the guest is available from `data`. For
+/// reads, the handler must supply exactly `len` bytes with `complete_read`
+/// before the next `run`.
+pub struct MmioExit<'a> {
+    kvm_run: *mut kvm_run,
+    _run: PhantomData<&'a mut kvm_run>,
+}
+
+impl<'a> MmioExit<'a> {
+    /// The caller must guarantee the same conditions as for
+    /// `VcpuExit::from_raw`, and that the last exit was `KVM_EXIT_MMIO`.
+    pub(crate) unsafe fn from_raw(kvm_run: *mut kvm_run) -> MmioExit<'a> {
+        MmioExit {
+            kvm_run,
+            _run: PhantomData,
+        }
+    }
+
+    fn mmio(&self) -> &kvm_run__bindgen_ty_1__bindgen_ty_6 {
+        unsafe { &(*self.kvm_run).__bindgen_anon_1.mmio }
+    }
+
+    fn mmio_mut(&mut self) -> &mut kvm_run__bindgen_ty_1__bindgen_ty_6 {
+        unsafe { &mut (*self.kvm_run).__bindgen_anon_1.mmio }
+    }
+
+    /// Returns the guest physical address accessed by the guest.
+    pub fn phys_addr(&self) -> u64 {
+        self.mmio().phys_addr
+    }
+
+    /// Returns the size of the access, in bytes.
+    pub fn len(&self) -> usize {
+        (self.mmio().len as usize).min(self.mmio().data.len())
+    }
+
+    /// Returns true if the access is zero bytes long.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true if the guest is writing to the address, and false if
+    /// it is reading from it.
+    pub fn is_write(&self) -> bool {
+        self.mmio().is_write != 0
+    }
+
+    /// Returns the data written by the guest, `len` bytes long. For reads,
+    /// the contents are unspecified until `complete_read` is called.
+    pub fn data(&self) -> &[u8] {
+        &self.mmio().data[..self.len()]
+    }
+
+    /// Supplies the data for a read by the guest. Returns an error if the
+    /// exit is for a write, or if `data` is not exactly `len` bytes long.
+    ///
+    /// ```ignore
+    /// if !mmio.is_write() {
+    ///     let value = device.read(mmio.phys_addr()) as u32;
+    ///     mmio.complete_read(&value.to_le_bytes()[..mmio.len()])?;
+    /// }
+    /// ```
+    pub fn complete_read(&mut self, data: &[u8]) -> Result<(), Error> {
+        if self.is_write() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "MMIO exit is for a write",
+            ));
+        }
+        let len = self.len();
+        if data.len() != len {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("MMIO read expects {} bytes, got {}", len, data.len()),
+            ));
+        }
+        self.mmio_mut().data[..len].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Debug for MmioExit<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MmioExit")
+            .field("phys_addr", &self.phys_addr())
+            .field("len", &self.len())
+            .field("is_write", &self.is_write())
+            .finish()
+    }
+}
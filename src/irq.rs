@@ -0,0 +1,348 @@
+// Copyright (C) 2018, Allison Randal
+//
+// Licensed under LGPL version 2 or any later version.
+
+//! Types for configuring interrupt delivery to the guest.
+
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+use linux::kvm_bindings::*;
+
+/// One of the in-kernel interrupt controller chips created by
+/// `VirtualMachine::create_irq_chip`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IrqChip {
+    /// The master 8259 PIC, handling IRQs 0-7.
+    PicMaster,
+    /// The slave 8259 PIC, handling IRQs 8-15.
+    PicSlave,
+    /// The IOAPIC, with 24 pins.
+    Ioapic,
+}
+
+impl IrqChip {
+    /// Returns the KVM identifier for the chip, one of the `KVM_IRQCHIP_*`
+    /// constants.
+    pub fn chip_id(&self) -> u32 {
+        match *self {
+            IrqChip::PicMaster => KVM_IRQCHIP_PIC_MASTER,
+            IrqChip::PicSlave => KVM_IRQCHIP_PIC_SLAVE,
+            IrqChip::Ioapic => KVM_IRQCHIP_IOAPIC,
+        }
+    }
+
+    /// Returns the number of input pins on the chip.
+    pub fn num_pins(&self) -> u32 {
+        match *self {
+            IrqChip::PicMaster | IrqChip::PicSlave => 8,
+            IrqChip::Ioapic => KVM_IOAPIC_NUM_PINS,
+        }
+    }
+}
+
+/// The saved state of one of the in-kernel interrupt controller chips, as
+/// returned by `VirtualMachine::get_irqchip`.
+#[derive(Copy, Clone)]
+pub enum IrqChipState {
+    /// The master 8259 PIC.
+    PicMaster(kvm_pic_state),
+    /// The slave 8259 PIC.
+    PicSlave(kvm_pic_state),
+    /// The IOAPIC.
+    Ioapic(kvm_ioapic_state),
+}
+
+impl IrqChipState {
+    /// Returns the chip this state belongs to.
+    pub fn chip(&self) -> IrqChip {
+        match *self {
+            IrqChipState::PicMaster(_) => IrqChip::PicMaster,
+            IrqChipState::PicSlave(_) => IrqChip::PicSlave,
+            IrqChipState::Ioapic(_) => IrqChip::Ioapic,
+        }
+    }
+
+    /// Returns the IOAPIC redirection table entry for a pin, or `None` if
+    /// this is not the IOAPIC state or the pin is out of range.
+    ///
+    /// ```ignore
+    /// let ioapic = vm.get_irqchip(IrqChip::Ioapic)?;
+    /// let entry = ioapic.ioapic_redirection_entry(4).unwrap();
+    /// println!("pin 4: vector {}, masked {}", entry.vector(), entry.is_masked());
+    /// ```
+    pub fn ioapic_redirection_entry(&self, pin: usize) -> Option<IoapicRedirectionEntry> {
+        match *self {
+            IrqChipState::Ioapic(ref ioapic) => ioapic
+                .redirtbl
+                .get(pin)
+                .map(|entry| IoapicRedirectionEntry(unsafe { entry.bits })),
+            _ => None,
+        }
+    }
+
+    /// Replaces the IOAPIC redirection table entry for a pin. Returns an
+    /// error if this is not the IOAPIC state or the pin is out of range.
+    pub fn set_ioapic_redirection_entry(
+        &mut self,
+        pin: usize,
+        entry: IoapicRedirectionEntry,
+    ) -> Result<(), Error> {
+        match *self {
+            IrqChipState::Ioapic(ref mut ioapic) => match ioapic.redirtbl.get_mut(pin) {
+                Some(redirtbl) => {
+                    redirtbl.bits = entry.bits();
+                    Ok(())
+                }
+                None => Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "IOAPIC pin out of range",
+                )),
+            },
+            _ => Err(Error::new(ErrorKind::InvalidInput, "not an IOAPIC state")),
+        }
+    }
+}
+
+impl fmt::Debug for IrqChipState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IrqChipState::PicMaster(ref pic) => f.debug_tuple("PicMaster").field(pic).finish(),
+            IrqChipState::PicSlave(ref pic) => f.debug_tuple("PicSlave").field(pic).finish(),
+            IrqChipState::Ioapic(ref ioapic) => {
+                let redirtbl: Vec<IoapicRedirectionEntry> = ioapic
+                    .redirtbl
+                    .iter()
+                    .map(|entry| IoapicRedirectionEntry(unsafe { entry.bits }))
+                    .collect();
+                f.debug_struct("Ioapic")
+                    .field("base_address", &ioapic.base_address)
+                    .field("ioregsel", &ioapic.ioregsel)
+                    .field("id", &ioapic.id)
+                    .field("irr", &ioapic.irr)
+                    .field("redirtbl", &redirtbl)
+                    .finish()
+            }
+        }
+    }
+}
+
+/// An IOAPIC redirection table entry, which decides how an interrupt on an
+/// IOAPIC pin is delivered to the local APICs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IoapicRedirectionEntry(u64);
+
+impl IoapicRedirectionEntry {
+    const VECTOR_MASK: u64 = 0xff;
+    const DELIVERY_MODE_SHIFT: u64 = 8;
+    const DELIVERY_MODE_MASK: u64 = 0x7 << IoapicRedirectionEntry::DELIVERY_MODE_SHIFT;
+    const DEST_MODE_LOGICAL: u64 = 1 << 11;
+    const DELIVERY_PENDING: u64 = 1 << 12;
+    const ACTIVE_LOW: u64 = 1 << 13;
+    const REMOTE_IRR: u64 = 1 << 14;
+    const LEVEL_TRIGGERED: u64 = 1 << 15;
+    const MASKED: u64 = 1 << 16;
+    const DEST_ID_SHIFT: u64 = 56;
+    const DEST_ID_MASK: u64 = 0xff << IoapicRedirectionEntry::DEST_ID_SHIFT;
+
+    /// Creates an entry from its raw 64 bit value.
+    pub fn from_bits(bits: u64) -> IoapicRedirectionEntry {
+        IoapicRedirectionEntry(bits)
+    }
+
+    /// Returns the raw 64 bit value of the entry.
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns the interrupt vector delivered to the guest.
+    pub fn vector(&self) -> u8 {
+        (self.0 & IoapicRedirectionEntry::VECTOR_MASK) as u8
+    }
+
+    /// Sets the interrupt vector delivered to the guest.
+    pub fn set_vector(&mut self, vector: u8) {
+        self.0 = (self.0 & !IoapicRedirectionEntry::VECTOR_MASK) | vector as u64;
+    }
+
+    /// Returns the delivery mode (0 fixed, 1 lowest priority, 2 SMI, 4 NMI,
+    /// 5 INIT, 7 ExtINT).
+    pub fn delivery_mode(&self) -> u8 {
+        ((self.0 & IoapicRedirectionEntry::DELIVERY_MODE_MASK)
+            >> IoapicRedirectionEntry::DELIVERY_MODE_SHIFT) as u8
+    }
+
+    /// Returns true if the destination is a logical APIC ID, and false if
+    /// it is a physical APIC ID.
+    pub fn is_logical_dest(&self) -> bool {
+        self.0 & IoapicRedirectionEntry::DEST_MODE_LOGICAL != 0
+    }
+
+    /// Returns true if the interrupt is waiting to be delivered.
+    pub fn is_delivery_pending(&self) -> bool {
+        self.0 & IoapicRedirectionEntry::DELIVERY_PENDING != 0
+    }
+
+    /// Returns true if the pin is active low.
+    pub fn is_active_low(&self) -> bool {
+        self.0 & IoapicRedirectionEntry::ACTIVE_LOW != 0
+    }
+
+    /// Returns true if a level triggered interrupt has been accepted by a
+    /// local APIC, and is waiting for an end of interrupt.
+    pub fn remote_irr(&self) -> bool {
+        self.0 & IoapicRedirectionEntry::REMOTE_IRR != 0
+    }
+
+    /// Returns true if the pin is level triggered, and false if it is edge
+    /// triggered.
+    pub fn is_level_triggered(&self) -> bool {
+        self.0 & IoapicRedirectionEntry::LEVEL_TRIGGERED != 0
+    }
+
+    /// Returns true if interrupts on the pin are masked.
+    pub fn is_masked(&self) -> bool {
+        self.0 & IoapicRedirectionEntry::MASKED != 0
+    }
+
+    /// Masks or unmasks interrupts on the pin.
+    pub fn set_masked(&mut self, masked: bool) {
+        if masked {
+            self.0 |= IoapicRedirectionEntry::MASKED;
+        } else {
+            self.0 &= !IoapicRedirectionEntry::MASKED;
+        }
+    }
+
+    /// Returns the destination APIC ID.
+    pub fn dest_id(&self) -> u8 {
+        ((self.0 & IoapicRedirectionEntry::DEST_ID_MASK) >> IoapicRedirectionEntry::DEST_ID_SHIFT)
+            as u8
+    }
+
+    /// Sets the destination APIC ID.
+    pub fn set_dest_id(&mut self, dest_id: u8) {
+        self.0 = (self.0 & !IoapicRedirectionEntry::DEST_ID_MASK)
+            | ((dest_id as u64) << IoapicRedirectionEntry::DEST_ID_SHIFT);
+    }
+}
+
+/// The outcome of injecting an interrupt into the guest.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IrqStatus {
+    /// The interrupt was delivered to the given number of virtual CPUs.
+    Delivered(u32),
+    /// The interrupt was merged with an identical interrupt that is still
+    /// pending in the guest.
+    Coalesced,
+    /// The interrupt was not delivered, because the guest has masked it or
+    /// no virtual CPU accepts it.
+    Blocked,
+}
+
+/// A table of GSI routing entries, for `VirtualMachine::set_gsi_routing`.
+///
+/// Each entry routes a GSI (global system interrupt) to an interrupt
+/// controller pin, an MSI message, or a Hyper-V synthetic interrupt. A GSI
+/// can have more than one entry, in which case an interrupt on the GSI is
+/// delivered to all of them. Setting a routing table replaces the previous
+/// one entirely, including the default routing set up by
+/// `create_irq_chip`.
+///
+/// ```ignore
+/// let mut routing = IrqRoutingTable::legacy();
+/// routing.add_msi(24, 0xfee0_0000, 0x4041, None);
+/// vm.set_gsi_routing(&routing)?;
+/// ```
+#[derive(Clone, Default)]
+pub struct IrqRoutingTable {
+    entries: Vec<kvm_irq_routing_entry>,
+}
+
+impl IrqRoutingTable {
+    /// Creates an empty routing table.
+    pub fn new() -> IrqRoutingTable {
+        IrqRoutingTable {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Creates a routing table with the same legacy routing that KVM sets
+    /// up in `create_irq_chip`: GSIs 0-15 are routed to both the PIC and
+    /// the IOAPIC pin of the same number, and GSIs 16-23 to the IOAPIC only.
+    pub fn legacy() -> IrqRoutingTable {
+        let mut table = IrqRoutingTable::new();
+        for gsi in 0..KVM_IOAPIC_NUM_PINS {
+            if gsi < 8 {
+                table.add_irqchip(gsi, IrqChip::PicMaster, gsi);
+            } else if gsi < 16 {
+                table.add_irqchip(gsi, IrqChip::PicSlave, gsi - 8);
+            }
+            table.add_irqchip(gsi, IrqChip::Ioapic, gsi);
+        }
+        table
+    }
+
+    /// Routes a GSI to a pin on one of the in-kernel interrupt controllers.
+    pub fn add_irqchip(&mut self, gsi: u32, chip: IrqChip, pin: u32) -> &mut Self {
+        let mut entry = IrqRoutingTable::entry(gsi, KVM_IRQ_ROUTING_IRQCHIP);
+        entry.u.irqchip = kvm_irq_routing_irqchip {
+            irqchip: chip.chip_id(),
+            pin,
+        };
+        self.entries.push(entry);
+        self
+    }
+
+    /// Routes a GSI to an MSI message, with the given address and data. The
+    /// `devid` identifies the requester, and is only needed on platforms
+    /// that require it.
+    pub fn add_msi(&mut self, gsi: u32, address: u64, data: u32, devid: Option<u32>) -> &mut Self {
+        let mut entry = IrqRoutingTable::entry(gsi, KVM_IRQ_ROUTING_MSI);
+        let mut msi = kvm_irq_routing_msi {
+            address_lo: address as u32,
+            address_hi: (address >> 32) as u32,
+            data,
+            ..Default::default()
+        };
+        if let Some(devid) = devid {
+            entry.flags = KVM_MSI_VALID_DEVID;
+            msi.__bindgen_anon_1.devid = devid;
+        }
+        entry.u.msi = msi;
+        self.entries.push(entry);
+        self
+    }
+
+    /// Routes a GSI to a Hyper-V synthetic interrupt source (SINT) on the
+    /// given virtual CPU.
+    pub fn add_hv_sint(&mut self, gsi: u32, vcpu: u32, sint: u32) -> &mut Self {
+        let mut entry = IrqRoutingTable::entry(gsi, KVM_IRQ_ROUTING_HV_SINT);
+        entry.u.hv_sint = kvm_irq_routing_hv_sint { vcpu, sint };
+        self.entries.push(entry);
+        self
+    }
+
+    /// Returns the raw routing entries.
+    pub fn entries(&self) -> &[kvm_irq_routing_entry] {
+        &self.entries
+    }
+
+    /// Returns the number of routing entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the table has no routing entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn entry(gsi: u32, type_: u32) -> kvm_irq_routing_entry {
+        kvm_irq_routing_entry {
+            gsi,
+            type_,
+            ..Default::default()
+        }
+    }
+}
@@ -22,6 +22,12 @@
 
 extern crate libc;
 
+pub mod cap;
+pub mod cpu;
+pub mod device;
+pub mod eventfd;
+pub mod exit;
+pub mod irq;
 pub mod linux;
 pub mod mem;
 pub mod system;
@@ -46,6 +46,8 @@ pub const KVM_GET_LAPIC: u64 =
 pub const KVM_SET_LAPIC: u64 =
     define_ioctl_op!(_IOC_WRITE, 0x8f, size_of::<kvm_lapic_state>() as u32);
 pub const KVM_CREATE_VCPU: u64 = define_ioctl_op!(_IOC_NONE, 0x41, 0);
+pub const KVM_GET_DIRTY_LOG: u64 =
+    define_ioctl_op!(_IOC_WRITE, 0x42, size_of::<kvm_dirty_log>() as u32);
 pub const KVM_SET_USER_MEMORY_REGION: u64 = define_ioctl_op!(
     _IOC_WRITE,
     0x46,
@@ -53,18 +55,90 @@ pub const KVM_SET_USER_MEMORY_REGION: u64 = define_ioctl_op!(
 );
 pub const KVM_SET_TSS_ADDR: u64 = define_ioctl_op!(_IOC_NONE, 0x47, 0);
 pub const KVM_CREATE_IRQCHIP: u64 = define_ioctl_op!(_IOC_NONE, 0x60, 0);
+pub const KVM_IRQ_LINE: u64 =
+    define_ioctl_op!(_IOC_WRITE, 0x61, size_of::<kvm_irq_level>() as u32);
+pub const KVM_GET_IRQCHIP: u64 = define_ioctl_op!(
+    _IOC_READ | _IOC_WRITE,
+    0x62,
+    size_of::<kvm_irqchip>() as u32
+);
+pub const KVM_SET_IRQCHIP: u64 = define_ioctl_op!(_IOC_READ, 0x63, size_of::<kvm_irqchip>() as u32);
+pub const KVM_IRQ_LINE_STATUS: u64 = define_ioctl_op!(
+    _IOC_READ | _IOC_WRITE,
+    0x67,
+    size_of::<kvm_irq_level>() as u32
+);
+pub const KVM_SET_GSI_ROUTING: u64 =
+    define_ioctl_op!(_IOC_WRITE, 0x6a, size_of::<kvm_irq_routing>() as u32);
+pub const KVM_IRQFD: u64 = define_ioctl_op!(_IOC_WRITE, 0x76, size_of::<kvm_irqfd>() as u32);
+pub const KVM_REINJECT_CONTROL: u64 = define_ioctl_op!(_IOC_NONE, 0x71, 0);
 pub const KVM_CREATE_PIT2: u64 =
     define_ioctl_op!(_IOC_WRITE, 0x77, size_of::<kvm_pit_config>() as u32);
+pub const KVM_IOEVENTFD: u64 =
+    define_ioctl_op!(_IOC_WRITE, 0x79, size_of::<kvm_ioeventfd>() as u32);
 pub const KVM_RUN: u64 = define_ioctl_op!(_IOC_NONE, 0x80, 0);
 pub const KVM_GET_REGS: u64 = define_ioctl_op!(_IOC_READ, 0x81, size_of::<kvm_regs>() as u32);
 pub const KVM_SET_REGS: u64 = define_ioctl_op!(_IOC_WRITE, 0x82, size_of::<kvm_regs>() as u32);
 pub const KVM_GET_SREGS: u64 = define_ioctl_op!(_IOC_READ, 0x83, size_of::<kvm_sregs>() as u32);
 pub const KVM_SET_SREGS: u64 = define_ioctl_op!(_IOC_WRITE, 0x84, size_of::<kvm_sregs>() as u32);
+pub const KVM_TRANSLATE: u64 = define_ioctl_op!(
+    _IOC_READ | _IOC_WRITE,
+    0x85,
+    size_of::<kvm_translation>() as u32
+);
+pub const KVM_INTERRUPT: u64 =
+    define_ioctl_op!(_IOC_WRITE, 0x86, size_of::<kvm_interrupt>() as u32);
 pub const KVM_GET_MSRS: u64 =
     define_ioctl_op!(_IOC_READ | _IOC_WRITE, 0x88, size_of::<kvm_msrs>() as u32);
 pub const KVM_SET_MSRS: u64 = define_ioctl_op!(_IOC_WRITE, 0x89, size_of::<kvm_msrs>() as u32);
+pub const KVM_SET_SIGNAL_MASK: u64 =
+    define_ioctl_op!(_IOC_WRITE, 0x8b, size_of::<kvm_signal_mask>() as u32);
 pub const KVM_GET_FPU: u64 = define_ioctl_op!(_IOC_READ, 0x8c, size_of::<kvm_fpu>() as u32);
 pub const KVM_SET_FPU: u64 = define_ioctl_op!(_IOC_WRITE, 0x8d, size_of::<kvm_fpu>() as u32);
 pub const KVM_SET_CPUID2: u64 = define_ioctl_op!(_IOC_WRITE, 0x90, size_of::<kvm_cpuid2>() as u32);
 pub const KVM_GET_CPUID2: u64 =
     define_ioctl_op!(_IOC_READ | _IOC_WRITE, 0x91, size_of::<kvm_cpuid2>() as u32);
+pub const KVM_GET_PIT2: u64 = define_ioctl_op!(_IOC_READ, 0x9f, size_of::<kvm_pit_state2>() as u32);
+pub const KVM_GET_MP_STATE: u64 =
+    define_ioctl_op!(_IOC_READ, 0x98, size_of::<kvm_mp_state>() as u32);
+pub const KVM_SET_MP_STATE: u64 =
+    define_ioctl_op!(_IOC_WRITE, 0x99, size_of::<kvm_mp_state>() as u32);
+pub const KVM_NMI: u64 = define_ioctl_op!(_IOC_NONE, 0x9a, 0);
+pub const KVM_SET_GUEST_DEBUG: u64 =
+    define_ioctl_op!(_IOC_WRITE, 0x9b, size_of::<kvm_guest_debug>() as u32);
+pub const KVM_GET_VCPU_EVENTS: u64 =
+    define_ioctl_op!(_IOC_READ, 0x9f, size_of::<kvm_vcpu_events>() as u32);
+pub const KVM_SET_PIT2: u64 =
+    define_ioctl_op!(_IOC_WRITE, 0xa0, size_of::<kvm_pit_state2>() as u32);
+pub const KVM_SET_VCPU_EVENTS: u64 =
+    define_ioctl_op!(_IOC_WRITE, 0xa0, size_of::<kvm_vcpu_events>() as u32);
+pub const KVM_GET_DEBUGREGS: u64 =
+    define_ioctl_op!(_IOC_READ, 0xa1, size_of::<kvm_debugregs>() as u32);
+pub const KVM_SET_DEBUGREGS: u64 =
+    define_ioctl_op!(_IOC_WRITE, 0xa2, size_of::<kvm_debugregs>() as u32);
+pub const KVM_ENABLE_CAP: u64 =
+    define_ioctl_op!(_IOC_WRITE, 0xa3, size_of::<kvm_enable_cap>() as u32);
+pub const KVM_GET_XSAVE: u64 = define_ioctl_op!(_IOC_READ, 0xa4, size_of::<kvm_xsave>() as u32);
+pub const KVM_SIGNAL_MSI: u64 = define_ioctl_op!(_IOC_WRITE, 0xa5, size_of::<kvm_msi>() as u32);
+pub const KVM_SET_XSAVE: u64 = define_ioctl_op!(_IOC_WRITE, 0xa5, size_of::<kvm_xsave>() as u32);
+pub const KVM_GET_XCRS: u64 = define_ioctl_op!(_IOC_READ, 0xa6, size_of::<kvm_xcrs>() as u32);
+pub const KVM_SET_XCRS: u64 = define_ioctl_op!(_IOC_WRITE, 0xa7, size_of::<kvm_xcrs>() as u32);
+pub const KVM_CLEAR_DIRTY_LOG: u64 = define_ioctl_op!(
+    _IOC_READ | _IOC_WRITE,
+    0xc0,
+    size_of::<kvm_clear_dirty_log>() as u32
+);
+pub const KVM_RESET_DIRTY_RINGS: u64 = define_ioctl_op!(_IOC_NONE, 0xc7, 0);
+pub const KVM_SMI: u64 = define_ioctl_op!(_IOC_NONE, 0xb7, 0);
+pub const KVM_GET_XSAVE2: u64 = define_ioctl_op!(_IOC_READ, 0xcf, size_of::<kvm_xsave>() as u32);
+pub const KVM_CREATE_DEVICE: u64 = define_ioctl_op!(
+    _IOC_READ | _IOC_WRITE,
+    0xe0,
+    size_of::<kvm_create_device>() as u32
+);
+pub const KVM_SET_DEVICE_ATTR: u64 =
+    define_ioctl_op!(_IOC_WRITE, 0xe1, size_of::<kvm_device_attr>() as u32);
+pub const KVM_GET_DEVICE_ATTR: u64 =
+    define_ioctl_op!(_IOC_WRITE, 0xe2, size_of::<kvm_device_attr>() as u32);
+pub const KVM_HAS_DEVICE_ATTR: u64 =
+    define_ioctl_op!(_IOC_WRITE, 0xe3, size_of::<kvm_device_attr>() as u32);
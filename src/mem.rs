@@ -27,3 +27,140 @@ pub trait MemorySlot {
     /// Returns the address of the start of the memory slot in the host, as an integer value.
     fn host_address(&self) -> u64;
 }
+
+/// The size of a guest page, in bytes, as tracked by dirty page logging.
+pub const PAGE_SIZE: usize = 4096;
+
+const BITS_PER_WORD: usize = 64;
+
+/// A snapshot of the dirty page bitmap for a memory slot, as returned by
+/// `VirtualMachine::get_dirty_log`.
+///
+/// Each bit represents one page of the slot, starting from the first page
+/// at the guest address of the slot. A set bit means the guest wrote to the
+/// page since the last time the dirty log was fetched (or cleared).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirtyLog {
+    slot_id: u32,
+    guest_address: u64,
+    num_pages: usize,
+    bitmap: Vec<u64>,
+}
+
+impl DirtyLog {
+    /// Creates an empty bitmap covering all pages of the memory slot.
+    pub fn new(slot: &MemorySlot) -> DirtyLog {
+        let num_pages = slot.memory_size().div_ceil(PAGE_SIZE);
+        let num_words = num_pages.div_ceil(BITS_PER_WORD);
+        DirtyLog {
+            slot_id: slot.slot_id(),
+            guest_address: slot.guest_address(),
+            num_pages,
+            bitmap: vec![0; num_words],
+        }
+    }
+
+    /// Returns the identifier of the memory slot the bitmap belongs to.
+    pub fn slot_id(&self) -> u32 {
+        self.slot_id
+    }
+
+    /// Returns the number of pages covered by the bitmap.
+    pub fn num_pages(&self) -> usize {
+        self.num_pages
+    }
+
+    /// Returns the raw bitmap, with the first page in the least significant
+    /// bit of the first word, as used by KVM.
+    pub fn as_slice(&self) -> &[u64] {
+        &self.bitmap
+    }
+
+    /// Returns the raw bitmap, for passing to KVM.
+    pub fn as_mut_slice(&mut self) -> &mut [u64] {
+        &mut self.bitmap
+    }
+
+    /// Returns true if the page at the given index in the slot is dirty.
+    /// Pages beyond the end of the slot are never dirty.
+    pub fn is_dirty(&self, page: usize) -> bool {
+        page < self.num_pages
+            && self.bitmap[page / BITS_PER_WORD] & (1 << (page % BITS_PER_WORD)) != 0
+    }
+
+    /// Returns the number of dirty pages in the bitmap.
+    pub fn count(&self) -> usize {
+        self.bitmap
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// Returns an iterator over the indices of dirty pages, relative to the
+    /// start of the slot, in ascending order.
+    pub fn dirty_pages(&self) -> DirtyPages<'_> {
+        DirtyPages {
+            bitmap: &self.bitmap,
+            index: 0,
+            word: self.bitmap.first().cloned().unwrap_or(0),
+        }
+    }
+
+    /// Returns an iterator over the guest physical addresses of the start
+    /// of each dirty page, in ascending order.
+    ///
+    /// ```ignore
+    /// let log = vm.get_dirty_log(&slot)?;
+    /// for address in log.dirty_addresses() {
+    ///     copy_page(address);
+    /// }
+    /// ```
+    pub fn dirty_addresses<'a>(&'a self) -> impl Iterator<Item = u64> + 'a {
+        let guest_address = self.guest_address;
+        self.dirty_pages()
+            .map(move |page| guest_address + (page * PAGE_SIZE) as u64)
+    }
+}
+
+/// An iterator over the dirty pages in a `DirtyLog`.
+#[derive(Debug, Clone)]
+pub struct DirtyPages<'a> {
+    bitmap: &'a [u64],
+    index: usize,
+    word: u64,
+}
+
+impl<'a> Iterator for DirtyPages<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while self.word == 0 {
+            self.index += 1;
+            if self.index >= self.bitmap.len() {
+                return None;
+            }
+            self.word = self.bitmap[self.index];
+        }
+        let bit = self.word.trailing_zeros() as usize;
+        // Clear the lowest set bit.
+        self.word &= self.word - 1;
+        Some(self.index * BITS_PER_WORD + bit)
+    }
+}
+
+/// The result of translating a guest virtual address, as returned by
+/// `VirtualCPU::translate`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Translation {
+    /// The guest physical address the virtual address maps to. Only
+    /// meaningful if `valid` is set.
+    pub physical_address: u64,
+    /// The virtual address is mapped in the current page tables.
+    pub valid: bool,
+    /// The mapping allows writes. KVM on x86 always reports mappings as
+    /// writeable.
+    pub writeable: bool,
+    /// The mapping is accessible from user mode. KVM on x86 always reports
+    /// mappings as supervisor only.
+    pub usermode: bool,
+}
@@ -21,9 +21,11 @@ use std::os::raw::c_char;
 use std::os::unix::io::{AsRawFd, FromRawFd};
 use utils::{KVMCpuid2Wrapper, KVMMSRListWrapper};
 
-use linux::kvm_bindings::{
-    kvm_cpuid_entry2, KVM_CAP_IRQCHIP, KVM_CAP_SET_TSS_ADDR, KVM_CAP_USER_MEMORY,
-};
+use cap::{Capabilities, Capability};
+use cpu::XstateBv;
+use device;
+
+use linux::kvm_bindings::{kvm_cpuid_entry2, KVM_X86_GRP_SYSTEM, KVM_X86_XCOMP_GUEST_SUPP};
 
 use linux::kvm_ioctl::{
     KVM_CHECK_EXTENSION, KVM_CREATE_VM, KVM_GET_API_VERSION, KVM_GET_EMULATED_CPUID,
@@ -133,8 +135,24 @@ impl KVMSystem {
         }
     }
 
-    fn check_extension(&self, capability: u32) -> Result<i32, Error> {
-        let result = unsafe { ioctl(self.ioctl.as_raw_fd(), KVM_CHECK_EXTENSION, capability) };
+    /// Check whether this KVM API supports a capability. The result is 0
+    /// if the capability is not supported, and otherwise 1 or a value
+    /// specific to the capability, such as the maximum number of memory
+    /// slots for `Capability::NrMemslots`.
+    ///
+    ///     # use libkvm::cap::*;
+    ///     # use libkvm::system::*;
+    ///     # let system = KVMSystem::new().expect("failed to connect to KVM");
+    ///     let result = system.check_extension(Capability::Irqchip);
+
+    pub fn check_extension(&self, capability: Capability) -> Result<i32, Error> {
+        let result = unsafe {
+            ioctl(
+                self.ioctl.as_raw_fd(),
+                KVM_CHECK_EXTENSION,
+                capability.to_raw(),
+            )
+        };
         if result > -1 {
             return Ok(result);
         } else {
@@ -142,6 +160,18 @@ impl KVMSystem {
         }
     }
 
+    /// Check every capability listed in `Capability`, and return a snapshot
+    /// of their values.
+    ///
+    ///     # use libkvm::system::*;
+    ///     # let system = KVMSystem::new().expect("failed to connect to KVM");
+    ///     let caps = system.capabilities().expect("failed to check capabilities");
+    ///     let max_vcpus = caps.max_vcpus();
+
+    pub fn capabilities(&self) -> Result<Capabilities, Error> {
+        Capabilities::probe(|cap| self.check_extension(cap))
+    }
+
     /// Check whether this KVM API supports the capability to create
     /// interrupt controller models in the Kernel.
     ///
@@ -150,7 +180,7 @@ impl KVMSystem {
     ///     let result = system.check_cap_irqchip();
 
     pub fn check_cap_irqchip(&self) -> Result<i32, Error> {
-        self.check_extension(KVM_CAP_IRQCHIP)
+        self.check_extension(Capability::Irqchip)
     }
 
     /// Check whether this KVM API supports the capability for fine
@@ -161,11 +191,96 @@ impl KVMSystem {
     ///     let result = system.check_cap_user_memory();
 
     pub fn check_cap_user_memory(&self) -> Result<i32, Error> {
-        self.check_extension(KVM_CAP_USER_MEMORY)
+        self.check_extension(Capability::UserMemory)
     }
 
     pub fn check_cap_set_tss_address(&self) -> Result<i32, Error> {
-        self.check_extension(KVM_CAP_SET_TSS_ADDR)
+        self.check_extension(Capability::SetTssAddr)
+    }
+
+    /// Check whether this KVM API supports injecting NMIs into a virtual
+    /// CPU from userspace.
+    ///
+    ///     # use libkvm::system::*;
+    ///     # let system = KVMSystem::new().expect("failed to connect to KVM");
+    ///     let result = system.check_cap_user_nmi();
+
+    pub fn check_cap_user_nmi(&self) -> Result<i32, Error> {
+        self.check_extension(Capability::UserNmi)
+    }
+
+    /// Check whether this KVM API supports system management mode, and
+    /// injecting SMIs into a virtual CPU from userspace.
+    ///
+    ///     # use libkvm::system::*;
+    ///     # let system = KVMSystem::new().expect("failed to connect to KVM");
+    ///     let result = system.check_cap_x86_smm();
+
+    pub fn check_cap_x86_smm(&self) -> Result<i32, Error> {
+        self.check_extension(Capability::X86Smm)
+    }
+
+    /// Check whether this KVM API supports `KVM_GET_XSAVE2`, returning the
+    /// size in bytes of the XSAVE buffer it needs, or 0 if it is not
+    /// supported.
+    ///
+    ///     # use libkvm::system::*;
+    ///     # let system = KVMSystem::new().expect("failed to connect to KVM");
+    ///     let result = system.check_cap_xsave2();
+
+    pub fn check_cap_xsave2(&self) -> Result<i32, Error> {
+        self.check_extension(Capability::Xsave2)
+    }
+
+    /// Check whether this KVM API supports a system attribute, such as
+    /// `KVM_X86_XCOMP_GUEST_SUPP` in the `KVM_X86_GRP_SYSTEM` group.
+    ///
+    ///     # use libkvm::linux::kvm_bindings::*;
+    ///     # use libkvm::system::*;
+    ///     # let system = KVMSystem::new().expect("failed to connect to KVM");
+    ///     let result = system.has_attr(KVM_X86_GRP_SYSTEM, KVM_X86_XCOMP_GUEST_SUPP as u64);
+
+    pub fn has_attr(&self, group: u32, attr: u64) -> Result<bool, Error> {
+        device::has_attr(self.ioctl.as_raw_fd(), group, attr)
+    }
+
+    /// Fetch the value of a system attribute that holds at most 64 bits,
+    /// such as `KVM_X86_XCOMP_GUEST_SUPP`. Fails with
+    /// `ErrorKind::InvalidInput` for other attributes, which can be read
+    /// with `get_attr_raw`.
+
+    pub fn get_attr(&self, group: u32, attr: u64) -> Result<u64, Error> {
+        device::get_attr(
+            self.ioctl.as_raw_fd(),
+            group,
+            attr,
+            device::SYSTEM_U64_ATTRS,
+        )
+    }
+
+    /// Fetch the value of a system attribute into the buffer at `addr`.
+    ///
+    /// # Safety
+    ///
+    /// KVM writes as many bytes to `addr` as the attribute holds. The caller
+    /// must guarantee that `addr` points to a writable buffer large enough
+    /// for the attribute.
+
+    pub unsafe fn get_attr_raw(&self, group: u32, attr: u64, addr: u64) -> Result<(), Error> {
+        device::get_attr_raw(self.ioctl.as_raw_fd(), group, attr, addr)
+    }
+
+    /// Fetch the XSAVE state components that KVM can expose to guests,
+    /// from the `KVM_X86_XCOMP_GUEST_SUPP` system attribute. Fails with
+    /// ENXIO if KVM does not report the attribute.
+    ///
+    ///     # use libkvm::system::*;
+    ///     # let system = KVMSystem::new().expect("failed to connect to KVM");
+    ///     let result = system.supported_xcomp_features();
+
+    pub fn supported_xcomp_features(&self) -> Result<XstateBv, Error> {
+        let features = self.get_attr(KVM_X86_GRP_SYSTEM, KVM_X86_XCOMP_GUEST_SUPP as u64)?;
+        Ok(XstateBv::from_bits(features))
     }
 
     /// Fetch the size of the shared memory region that KVM uses to
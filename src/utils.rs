@@ -2,7 +2,11 @@
 //
 // Licensed under LGPL version 2 or any later version.
 
-use linux::kvm_bindings::{kvm_cpuid2, kvm_cpuid_entry2, kvm_msr_entry, kvm_msr_list, kvm_msrs};
+use libc;
+use linux::kvm_bindings::{
+    kvm_cpuid2, kvm_cpuid_entry2, kvm_irq_routing, kvm_irq_routing_entry, kvm_msr_entry,
+    kvm_msr_list, kvm_msrs, kvm_signal_mask,
+};
 use std;
 
 pub struct KVMCpuid2Wrapper {
@@ -153,3 +157,76 @@ impl KVMMSRListWrapper {
         self.buf.as_mut_ptr() as *mut kvm_msr_list
     }
 }
+
+pub struct KVMIrqRoutingWrapper {
+    buf: Vec<u8>,
+    kvm_irq_routing: *mut kvm_irq_routing,
+}
+
+impl KVMIrqRoutingWrapper {
+    pub fn new(num_entries: u32) -> KVMIrqRoutingWrapper {
+        let size = std::mem::size_of::<kvm_irq_routing>()
+            + std::mem::size_of::<kvm_irq_routing_entry>() * num_entries as usize;
+        let buf: Vec<u8> = vec![0; size];
+        let kvm_irq_routing: &mut kvm_irq_routing =
+            unsafe { &mut *(buf.as_ptr() as *mut kvm_irq_routing) };
+        kvm_irq_routing.nr = num_entries;
+
+        KVMIrqRoutingWrapper {
+            buf: buf,
+            kvm_irq_routing: kvm_irq_routing,
+        }
+    }
+
+    pub fn from_routing_entries(entries: &[kvm_irq_routing_entry]) -> KVMIrqRoutingWrapper {
+        let mut kvm_irq_routing = KVMIrqRoutingWrapper::new(entries.len() as u32);
+        kvm_irq_routing.copy_entries(entries);
+        kvm_irq_routing
+    }
+
+    fn copy_entries(&mut self, entries: &[kvm_irq_routing_entry]) {
+        unsafe {
+            (*self.kvm_irq_routing)
+                .entries
+                .as_mut_slice((*self.kvm_irq_routing).nr as usize)
+        }.copy_from_slice(entries);
+    }
+
+    pub fn as_ptr(&self) -> *const kvm_irq_routing {
+        self.buf.as_ptr() as *const kvm_irq_routing
+    }
+}
+
+// The size of the signal set the kernel expects in `kvm_signal_mask`,
+// which is smaller than the libc `sigset_t`.
+const KERNEL_SIGSET_SIZE: usize = 8;
+
+pub struct KVMSignalMaskWrapper {
+    buf: Vec<u8>,
+}
+
+impl KVMSignalMaskWrapper {
+    pub fn from_sigset(sigset: &libc::sigset_t) -> KVMSignalMaskWrapper {
+        let size = std::mem::size_of::<kvm_signal_mask>() + KERNEL_SIGSET_SIZE;
+        let mut buf: Vec<u8> = vec![0; size];
+        let kvm_signal_mask: &mut kvm_signal_mask =
+            unsafe { &mut *(buf.as_mut_ptr() as *mut kvm_signal_mask) };
+        kvm_signal_mask.len = KERNEL_SIGSET_SIZE as u32;
+        unsafe {
+            let sigset_bytes = std::slice::from_raw_parts(
+                sigset as *const libc::sigset_t as *const u8,
+                KERNEL_SIGSET_SIZE,
+            );
+            kvm_signal_mask
+                .sigset
+                .as_mut_slice(KERNEL_SIGSET_SIZE)
+                .copy_from_slice(sigset_bytes);
+        }
+
+        KVMSignalMaskWrapper { buf: buf }
+    }
+
+    pub fn as_ptr(&self) -> *const kvm_signal_mask {
+        self.buf.as_ptr() as *const kvm_signal_mask
+    }
+}
@@ -9,43 +9,117 @@ extern crate libc;
 use self::libc::ioctl;
 use std;
 use std::fs::File;
-use std::io::Error;
+use std::io::{Error, ErrorKind};
+use std::mem::size_of;
 use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{fence, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, Once};
 
+use cap::VcpuCap;
+use cpu::{DebugRegs, GuestDebug, MpState, VcpuEvents, Xsave};
+use device;
+use exit::{IoExit, MmioExit, VcpuExit};
 use linux::kvm_bindings::{
-    kvm_cpuid_entry2, kvm_fpu, kvm_lapic_state, kvm_msr_entry, kvm_regs, kvm_run, kvm_sregs,
+    kvm_cpuid_entry2, kvm_debugregs, kvm_dirty_gfn, kvm_fpu, kvm_interrupt, kvm_lapic_state,
+    kvm_mp_state, kvm_msr_entry, kvm_regs, kvm_run, kvm_sregs, kvm_translation, kvm_vcpu_events,
+    kvm_xcrs, kvm_xsave, KVM_DIRTY_GFN_F_DIRTY, KVM_DIRTY_GFN_F_MASK, KVM_DIRTY_GFN_F_RESET,
+    KVM_DIRTY_LOG_PAGE_OFFSET, KVM_EXIT_IO, KVM_EXIT_MMIO,
 };
 use linux::kvm_ioctl::{
-    KVM_GET_CPUID2, KVM_GET_FPU, KVM_GET_LAPIC, KVM_GET_MSRS, KVM_GET_REGS, KVM_GET_SREGS, KVM_RUN,
-    KVM_SET_CPUID2, KVM_SET_FPU, KVM_SET_LAPIC, KVM_SET_MSRS, KVM_SET_REGS, KVM_SET_SREGS,
+    KVM_ENABLE_CAP, KVM_GET_CPUID2, KVM_GET_DEBUGREGS, KVM_GET_FPU, KVM_GET_LAPIC,
+    KVM_GET_MP_STATE, KVM_GET_MSRS, KVM_GET_REGS, KVM_GET_SREGS, KVM_GET_VCPU_EVENTS, KVM_GET_XCRS,
+    KVM_GET_XSAVE, KVM_GET_XSAVE2, KVM_INTERRUPT, KVM_NMI, KVM_RUN, KVM_SET_CPUID2,
+    KVM_SET_DEBUGREGS, KVM_SET_FPU, KVM_SET_GUEST_DEBUG, KVM_SET_LAPIC, KVM_SET_MP_STATE,
+    KVM_SET_MSRS, KVM_SET_REGS, KVM_SET_SIGNAL_MASK, KVM_SET_SREGS, KVM_SET_VCPU_EVENTS,
+    KVM_SET_XCRS, KVM_SET_XSAVE, KVM_SMI, KVM_TRANSLATE,
 };
+use mem::{Translation, PAGE_SIZE};
 use system::KVMSystem;
-use utils::{KVMCpuid2Wrapper, KVMMSRSWrapper};
+use utils::{KVMCpuid2Wrapper, KVMMSRSWrapper, KVMSignalMaskWrapper};
 
 /// The VirtualCPU module handles KVM virtual CPU operations.
 /// It owns the filehandle for these operations.
+///
+/// A `VirtualCPU` stays on the thread that created it, which is the thread
+/// that runs the guest CPU. Other threads use a `VcpuHandle` to stop it.
 pub struct VirtualCPU {
     ioctl: File,
-    kvm_run: *mut kvm_run,
-    vcpu_map_size: usize,
+    run_mapping: Arc<RunMapping>,
+    dirty_ring: *mut kvm_dirty_gfn,
+    dirty_ring_entries: u32,
+    dirty_ring_next: u32,
+    xsave2_size: usize,
+    user_nmi: bool,
+    smm: Option<bool>,
+}
+
+// Settings of the VM that a virtual CPU depends on, probed once when the
+// virtual CPU is created.
+#[derive(Debug, Copy, Clone, Default)]
+pub(crate) struct VcpuConfig {
+    // The number of entries in the dirty ring that the VM enabled, or 0 if
+    // the dirty ring is not enabled. The ring is mapped and harvested with
+    // this size, so it must match the VM.
+    pub(crate) dirty_ring_entries: u32,
+    // The size of the XSAVE area reported by KVM_CAP_XSAVE2 for the VM, or
+    // 0 if it is not supported or not known.
+    pub(crate) xsave2_size: usize,
+    // Whether KVM supports KVM_CAP_USER_NMI.
+    pub(crate) user_nmi: bool,
+    // Whether the VM supports KVM_CAP_X86_SMM, which depends on the VM type,
+    // or None if it is not known.
+    pub(crate) smm: Option<bool>,
 }
 
 impl VirtualCPU {
     /// Creates a new `VirtualCPU` from an existing filehandle for
-    /// virtual CPU operations.
+    /// virtual CPU operations. The dirty ring of the VM is not mapped for a
+    /// virtual CPU created this way, so use `VirtualMachine::create_vcpu`
+    /// for VMs that enable it. The capabilities that depend on the VM are
+    /// not known either, so `get_xsave2` only returns the 4096 byte area of
+    /// `get_xsave`.
     pub fn from_file(handle: File) -> Result<Self, Error> {
+        let kvm = KVMSystem::new()?;
+        let config = VcpuConfig {
+            dirty_ring_entries: 0,
+            xsave2_size: 0,
+            user_nmi: kvm.check_cap_user_nmi()? > 0,
+            smm: None,
+        };
+        VirtualCPU::from_file_with_config(handle, config)
+    }
+
+    // Creates a new `VirtualCPU` with the settings of the VM it belongs to.
+    pub(crate) fn from_file_with_config(handle: File, config: VcpuConfig) -> Result<Self, Error> {
         let kvm = KVMSystem::new()?;
         let vcpu_map_size = kvm.get_vcpu_mmap_size()?;
-        let kvm_run = VirtualCPU::map_kvm_run(&handle, vcpu_map_size)?;
+        let dirty_ring_entries = config.dirty_ring_entries;
+        let (kvm_run, dirty_ring) =
+            VirtualCPU::map_kvm_run(&handle, vcpu_map_size, dirty_ring_entries)?;
+
+        let run_mapping = Arc::new(RunMapping {
+            kvm_run: kvm_run,
+            map_size: vcpu_map_size,
+            thread: Mutex::new(Some(unsafe { libc::pthread_self() })),
+        });
 
         Ok(VirtualCPU {
             ioctl: handle,
-            vcpu_map_size: vcpu_map_size,
-            kvm_run: kvm_run,
+            run_mapping: run_mapping,
+            dirty_ring: dirty_ring,
+            dirty_ring_entries: dirty_ring_entries,
+            dirty_ring_next: 0,
+            xsave2_size: config.xsave2_size,
+            user_nmi: config.user_nmi,
+            smm: config.smm,
         })
     }
 
-    fn map_kvm_run(handle: &File, vcpu_map_size: usize) -> Result<*mut kvm_run, Error> {
+    fn map_kvm_run(
+        handle: &File,
+        vcpu_map_size: usize,
+        dirty_ring_entries: u32,
+    ) -> Result<(*mut kvm_run, *mut kvm_dirty_gfn), Error> {
         let address = unsafe {
             libc::mmap(
                 std::ptr::null_mut(),
@@ -58,24 +132,78 @@ impl VirtualCPU {
         };
 
         if address == libc::MAP_FAILED {
-            Err(Error::last_os_error())
+            return Err(Error::last_os_error());
+        }
+        if dirty_ring_entries == 0 {
+            return Ok((address as *mut kvm_run, std::ptr::null_mut()));
+        }
+
+        // The dirty ring is mapped from a fixed page offset of the virtual
+        // CPU filehandle, separately from the kvm_run structure.
+        let ring_address = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                VirtualCPU::dirty_ring_size(dirty_ring_entries),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                handle.as_raw_fd(),
+                (KVM_DIRTY_LOG_PAGE_OFFSET as usize * PAGE_SIZE) as libc::off_t,
+            )
+        };
+
+        if ring_address == libc::MAP_FAILED {
+            let error = Error::last_os_error();
+            unsafe { libc::munmap(address, vcpu_map_size) };
+            Err(error)
         } else {
-            Ok(address as *mut kvm_run)
+            Ok((address as *mut kvm_run, ring_address as *mut kvm_dirty_gfn))
         }
     }
 
+    fn dirty_ring_size(dirty_ring_entries: u32) -> usize {
+        dirty_ring_entries as usize * std::mem::size_of::<kvm_dirty_gfn>()
+    }
+
+    /// Returns the `kvm_run` structure shared with KVM. Prefer `run_exit`
+    /// for the exit data, and helpers such as `if_flag` for the other
+    /// fields.
+    ///
+    /// A `VcpuHandle` may set `immediate_exit` from another thread at any
+    /// time, so its value read through this reference is unspecified once
+    /// `handle` has been called.
     pub fn kvm_run(&self) -> &kvm_run {
-        unsafe { &*self.kvm_run }
+        unsafe { &*self.run_mapping.kvm_run }
     }
 
+    /// Returns the `kvm_run` structure shared with KVM, for modification.
+    /// Prefer `run_exit` for the exit data, and helpers such as
+    /// `request_interrupt_window` for the other fields.
+    ///
+    /// Panics if any `VcpuHandle` for the vCPU exists, as a handle may
+    /// write `immediate_exit` from another thread while the structure is
+    /// borrowed.
     pub fn kvm_run_mut(&mut self) -> &mut kvm_run {
-        unsafe { &mut *self.kvm_run }
+        assert!(
+            Arc::strong_count(&self.run_mapping) == 1,
+            "kvm_run_mut called while a VcpuHandle exists"
+        );
+        unsafe { &mut *self.run_mapping.kvm_run }
+    }
+
+    fn exit_reason(&self) -> u32 {
+        unsafe { (*self.run_mapping.kvm_run).exit_reason }
     }
 
     /// Runs the guest virtual CPU, and returns a `Result`. If the run
     /// operation fails, the `Result` unwraps as an `Error`. If it succeeds,
     /// the `Result` unwraps as a boolean true value.
     ///
+    /// If the run was interrupted by a signal or by `VcpuHandle::kick`
+    /// before the guest exited, the `Error` has the kind
+    /// `ErrorKind::Interrupted`, and the next run enters the guest again.
+    /// Callers that want to handle kicks as an exit use `run_exit`, which
+    /// returns `VcpuExit::Interrupted` instead.
+    ///
     /// ```ignore
     /// let result = vcpu.run();
     /// ```
@@ -83,11 +211,267 @@ impl VirtualCPU {
         let result = unsafe { ioctl(self.ioctl.as_raw_fd(), KVM_RUN, 0) };
         if result == 0 {
             return Ok(true);
+        }
+        let error = Error::last_os_error();
+        if error.kind() == ErrorKind::Interrupted {
+            // A kick only stops one run, so the next run enters the guest.
+            self.run_mapping.immediate_exit().store(0, Ordering::SeqCst);
+        }
+        return Err(error);
+    }
+
+    /// Runs the guest virtual CPU, and returns a `Result`. If the run
+    /// operation fails, the `Result` unwraps as an `Error`. If it succeeds,
+    /// the `Result` unwraps as a `VcpuExit` describing why the guest exited.
+    /// The exit borrows the virtual CPU, so any data the guest expects back
+    /// must be filled in before the next run.
+    ///
+    /// If the run was interrupted by a signal or by `VcpuHandle::kick`,
+    /// the exit is `VcpuExit::Interrupted`.
+    ///
+    /// ```ignore
+    /// match vcpu.run_exit().expect("failed to run VCPU") {
+    ///     VcpuExit::Hlt => println!("Halt"),
+    ///     exit => panic!("Unexpected exit: {:?}", exit),
+    /// }
+    /// ```
+    pub fn run_exit(&mut self) -> Result<VcpuExit<'_>, Error> {
+        if let Err(error) = self.run() {
+            if error.kind() == ErrorKind::Interrupted {
+                return Ok(VcpuExit::Interrupted);
+            }
+            return Err(error);
+        }
+
+        // Safe because the mapping lives as long as this VirtualCPU, and
+        // the exit holds a mutable borrow of it.
+        let run_mapping = &self.run_mapping;
+        Ok(unsafe { VcpuExit::from_raw(run_mapping.kvm_run, run_mapping.map_size) })
+    }
+
+    /// Returns a handle for stopping this virtual CPU from another thread.
+    ///
+    /// ```ignore
+    /// let handle = vcpu.handle();
+    /// thread::spawn(move || handle.kick().expect("failed to kick vCPU"));
+    /// ```
+    pub fn handle(&self) -> VcpuHandle {
+        install_kick_handler();
+        VcpuHandle {
+            run_mapping: self.run_mapping.clone(),
+        }
+    }
+
+    /// Sets the signals blocked while the guest runs, replacing the
+    /// thread's signal mask for the duration of each run, or restores the
+    /// thread's own signal mask with `None`. This lets the vCPU thread
+    /// block `VcpuHandle::signal` except while it is in the guest.
+    ///
+    /// ```ignore
+    /// let mut sigset: libc::sigset_t = unsafe { std::mem::zeroed() };
+    /// unsafe { libc::sigemptyset(&mut sigset) };
+    /// vcpu.set_signal_mask(Some(&sigset))?;
+    /// ```
+    pub fn set_signal_mask(&self, sigset: Option<&libc::sigset_t>) -> Result<(), Error> {
+        let kvm_signal_mask = sigset.map(KVMSignalMaskWrapper::from_sigset);
+        let mask_ptr = match kvm_signal_mask {
+            Some(ref mask) => mask.as_ptr(),
+            None => std::ptr::null(),
+        };
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_SET_SIGNAL_MASK, mask_ptr) };
+        if result == 0 {
+            return Ok(());
         } else {
             return Err(Error::last_os_error());
         }
     }
 
+    /// Returns the port I/O exit from the last run, or `None` if the last
+    /// exit was for some other reason. Handlers for `In` accesses write
+    /// their response into the exit's data before the next run.
+    ///
+    /// ```ignore
+    /// if let Some(mut io) = vcpu.io_exit() {
+    ///     for byte in io.iterations_mut() {
+    ///         byte[0] = 0xff;
+    ///     }
+    /// }
+    /// ```
+    pub fn io_exit(&mut self) -> Option<IoExit<'_>> {
+        if self.exit_reason() != KVM_EXIT_IO {
+            return None;
+        }
+
+        // Safe because the exit reason is checked, and the exit holds a
+        // mutable borrow of the mapping.
+        let run_mapping = &self.run_mapping;
+        Some(unsafe { IoExit::from_raw(run_mapping.kvm_run, run_mapping.map_size) })
+    }
+
+    /// Returns the memory mapped I/O exit from the last run, or `None` if
+    /// the last exit was for some other reason. Handlers for reads supply
+    /// their response with `complete_read` before the next run.
+    ///
+    /// ```ignore
+    /// if let Some(mut mmio) = vcpu.mmio_exit() {
+    ///     if !mmio.is_write() {
+    ///         let len = mmio.len();
+    ///         mmio.complete_read(&[0; 8][..len])?;
+    ///     }
+    /// }
+    /// ```
+    pub fn mmio_exit(&mut self) -> Option<MmioExit<'_>> {
+        if self.exit_reason() != KVM_EXIT_MMIO {
+            return None;
+        }
+
+        // Safe because the exit reason is checked, and the exit holds a
+        // mutable borrow of the mapping.
+        Some(unsafe { MmioExit::from_raw(self.run_mapping.kvm_run) })
+    }
+
+    /// Injects an external interrupt with the given vector into the guest,
+    /// for VMs that emulate the interrupt controller in userspace instead
+    /// of calling `create_irq_chip`. The guest must be ready to accept the
+    /// interrupt, as reported by `ready_for_interrupt_injection`.
+    ///
+    /// ```ignore
+    /// if vcpu.ready_for_interrupt_injection() {
+    ///     vcpu.interrupt(0x20)?;
+    /// } else {
+    ///     vcpu.request_interrupt_window(true);
+    /// }
+    /// ```
+    pub fn interrupt(&self, vector: u8) -> Result<(), Error> {
+        let interrupt = kvm_interrupt {
+            irq: u32::from(vector),
+        };
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_INTERRUPT, &interrupt) };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Injects a non-maskable interrupt into the guest. Fails with
+    /// `ErrorKind::Unsupported` if KVM does not support `KVM_CAP_USER_NMI`.
+    ///
+    /// ```ignore
+    /// // Watchdog expired, let the guest kernel dump its state.
+    /// vcpu.inject_nmi()?;
+    /// ```
+    pub fn inject_nmi(&self) -> Result<(), Error> {
+        if !self.user_nmi {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "KVM does not support KVM_CAP_USER_NMI",
+            ));
+        }
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_NMI, 0) };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Injects a system management interrupt into the guest, entering SMM
+    /// on the next run. Fails with `ErrorKind::Unsupported` if the VM does
+    /// not support `KVM_CAP_X86_SMM`.
+    pub fn inject_smi(&self) -> Result<(), Error> {
+        let unsupported = Error::new(
+            ErrorKind::Unsupported,
+            "KVM does not support KVM_CAP_X86_SMM",
+        );
+        if self.smm == Some(false) {
+            return Err(unsupported);
+        }
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_SMI, 0) };
+        if result == 0 {
+            return Ok(());
+        }
+        // Without the VM to probe, KVM only reports missing SMM support
+        // through the ioctl itself.
+        let error = Error::last_os_error();
+        let rejected = matches!(
+            error.raw_os_error(),
+            Some(libc::EINVAL) | Some(libc::ENOTTY)
+        );
+        if self.smm.is_none() && rejected {
+            return Err(unsupported);
+        } else {
+            return Err(error);
+        }
+    }
+
+    /// Asks KVM to exit with `VcpuExit::IrqWindowOpen` as soon as the guest
+    /// can accept an interrupt, or cancels the request.
+    pub fn request_interrupt_window(&mut self, request: bool) {
+        unsafe { (*self.run_mapping.kvm_run).request_interrupt_window = request as u8 };
+    }
+
+    /// Returns true if an interrupt can be injected with `interrupt`
+    /// before the next run, as reported by the last exit.
+    pub fn ready_for_interrupt_injection(&self) -> bool {
+        unsafe { (*self.run_mapping.kvm_run).ready_for_interrupt_injection != 0 }
+    }
+
+    /// Returns the value of the guest's interrupt flag at the last exit.
+    pub fn if_flag(&self) -> bool {
+        unsafe { (*self.run_mapping.kvm_run).if_flag != 0 }
+    }
+
+    /// Collects the pages logged as dirty in the virtual CPU's dirty ring
+    /// since the last harvest, as `(slot, offset)` pairs, where `offset` is
+    /// the page index relative to the start of the memory slot. The slot
+    /// number carries the address space ID in its upper 16 bits. Harvested
+    /// entries are marked for reuse, and are recycled by the next call to
+    /// `VirtualMachine::reset_dirty_rings`. Fails with
+    /// `ErrorKind::Unsupported` if the dirty ring is not mapped, either
+    /// because the VM did not enable it or because the virtual CPU was
+    /// created with `from_file`, which does not know the ring size.
+    ///
+    /// ```ignore
+    /// for (slot, page) in vcpu.harvest_dirty_ring()? {
+    ///     copy_page(slot, page);
+    /// }
+    /// vm.reset_dirty_rings()?;
+    /// ```
+    pub fn harvest_dirty_ring(&mut self) -> Result<Vec<(u32, u64)>, Error> {
+        if self.dirty_ring.is_null() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "dirty ring is not mapped for this virtual CPU",
+            ));
+        }
+
+        let mut pages = Vec::new();
+
+        loop {
+            let index = (self.dirty_ring_next % self.dirty_ring_entries) as usize;
+            // Safe because the index is within the ring mapping, which lives
+            // as long as this VirtualCPU. The flags are shared with KVM, so
+            // they are accessed with volatile operations and fences.
+            unsafe {
+                let entry = self.dirty_ring.add(index);
+                let flags = std::ptr::read_volatile(&(*entry).flags);
+                fence(Ordering::Acquire);
+                if flags & KVM_DIRTY_GFN_F_MASK != KVM_DIRTY_GFN_F_DIRTY {
+                    break;
+                }
+                pages.push((
+                    std::ptr::read_volatile(&(*entry).slot),
+                    std::ptr::read_volatile(&(*entry).offset),
+                ));
+                fence(Ordering::Release);
+                std::ptr::write_volatile(&mut (*entry).flags, KVM_DIRTY_GFN_F_RESET);
+            }
+            self.dirty_ring_next = self.dirty_ring_next.wrapping_add(1);
+        }
+        Ok(pages)
+    }
+
     pub fn get_kvm_regs(&self) -> Result<kvm_regs, Error> {
         let mut regs: kvm_regs = Default::default();
         let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_GET_REGS, &mut regs) };
@@ -217,11 +601,350 @@ impl VirtualCPU {
             return Err(Error::last_os_error());
         }
     }
+
+    /// Returns the multiprocessing state of the vCPU. Without an in-kernel
+    /// irqchip the vCPU is always `Runnable` or `Halted`.
+    ///
+    /// ```ignore
+    /// if vcpu.get_mp_state()? == MpState::Halted {
+    ///     println!("vCPU is waiting for an interrupt");
+    /// }
+    /// ```
+    pub fn get_mp_state(&self) -> Result<MpState, Error> {
+        let mut mp_state: kvm_mp_state = Default::default();
+        let result = unsafe { ioctl(self.ioctl.as_raw_fd(), KVM_GET_MP_STATE, &mut mp_state) };
+        if result == 0 {
+            return Ok(MpState::from_raw(mp_state.mp_state));
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Sets the multiprocessing state of the vCPU. States other than
+    /// `Runnable` require an in-kernel irqchip, for example to park an
+    /// application processor in `Uninitialized` until the guest starts it
+    /// with INIT and SIPI.
+    pub fn set_mp_state(&self, mp_state: MpState) -> Result<(), Error> {
+        let mp_state = kvm_mp_state {
+            mp_state: mp_state.to_raw(),
+        };
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_SET_MP_STATE, &mp_state) };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Translates a guest virtual address to a guest physical address,
+    /// using the current paging mode and page tables of the vCPU.
+    ///
+    /// ```ignore
+    /// let translation = vcpu.translate(regs.rip)?;
+    /// if translation.valid {
+    ///     println!("rip is at {:#x}", translation.physical_address);
+    /// }
+    /// ```
+    pub fn translate(&self, gva: u64) -> Result<Translation, Error> {
+        let mut translation = kvm_translation {
+            linear_address: gva,
+            ..Default::default()
+        };
+        let result = unsafe { ioctl(self.ioctl.as_raw_fd(), KVM_TRANSLATE, &mut translation) };
+        if result == 0 {
+            return Ok(Translation {
+                physical_address: translation.physical_address,
+                valid: translation.valid != 0,
+                writeable: translation.writeable != 0,
+                usermode: translation.usermode != 0,
+            });
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Enables, changes or disables guest debugging. While it is enabled,
+    /// single-steps and breakpoints exit to userspace with
+    /// `VcpuExit::Debug`.
+    ///
+    /// ```ignore
+    /// let mut debug = GuestDebug::new();
+    /// debug.software_breakpoints(true);
+    /// vcpu.set_guest_debug(&debug)?;
+    /// if let VcpuExit::Debug(debug_exit) = vcpu.run_exit()? {
+    ///     println!("breakpoint at {:#x}", debug_exit.pc);
+    /// }
+    /// ```
+    pub fn set_guest_debug(&self, debug: &GuestDebug) -> Result<(), Error> {
+        let debug = debug.to_raw();
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_SET_GUEST_DEBUG, &debug) };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Returns the pending and injected exceptions, interrupts, NMIs and
+    /// SMIs of the vCPU, along with its interrupt shadow and SIPI vector.
+    ///
+    /// ```ignore
+    /// let events = vcpu.get_vcpu_events()?;
+    /// if let Some(exception) = events.exception() {
+    ///     println!("exception {} queued", exception.vector);
+    /// }
+    /// ```
+    pub fn get_vcpu_events(&self) -> Result<VcpuEvents, Error> {
+        let mut events: kvm_vcpu_events = Default::default();
+        let result = unsafe { ioctl(self.ioctl.as_raw_fd(), KVM_GET_VCPU_EVENTS, &mut events) };
+        if result == 0 {
+            return Ok(VcpuEvents::from_raw(events));
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Restores the vCPU event state, usually saved with `get_vcpu_events`.
+    /// Only the optional parts of the state marked as valid in
+    /// `VcpuEvents::flags` are changed.
+    pub fn set_vcpu_events(&self, events: &VcpuEvents) -> Result<(), Error> {
+        let result =
+            unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_SET_VCPU_EVENTS, events.as_raw()) };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Returns the debug address, status and control registers of the vCPU.
+    ///
+    /// ```ignore
+    /// let debugregs = vcpu.get_debugregs()?;
+    /// for index in debugregs.dr6.breakpoints_hit() {
+    ///     println!("hit breakpoint at {:#x}", debugregs.db[index]);
+    /// }
+    /// ```
+    pub fn get_debugregs(&self) -> Result<DebugRegs, Error> {
+        let mut debugregs: kvm_debugregs = Default::default();
+        let result = unsafe { ioctl(self.ioctl.as_raw_fd(), KVM_GET_DEBUGREGS, &mut debugregs) };
+        if result == 0 {
+            return Ok(DebugRegs::from_raw(&debugregs));
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Sets the debug address, status and control registers of the vCPU.
+    /// The upper 32 bits of DR6 and DR7 must be zero.
+    pub fn set_debugregs(&self, debugregs: &DebugRegs) -> Result<(), Error> {
+        let debugregs = debugregs.to_raw();
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_SET_DEBUGREGS, &debugregs) };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Returns the XSAVE area of the vCPU, holding the x87, SSE, AVX and
+    /// later register state that fits in 4096 bytes. Use `get_xsave2` for
+    /// guests that may use larger state components.
+    pub fn get_xsave(&self) -> Result<Xsave, Error> {
+        let mut xsave = Xsave::with_size(size_of::<kvm_xsave>());
+        let result = unsafe { ioctl(self.ioctl.as_raw_fd(), KVM_GET_XSAVE, xsave.as_mut_ptr()) };
+        if result == 0 {
+            return Ok(xsave);
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Restores the XSAVE area of the vCPU, in the same way as `set_xsave2`.
+    /// This fails if the vCPU state does not fit in an area returned by
+    /// `get_xsave`, in which case use `get_xsave2` to save it.
+    pub fn set_xsave(&self, xsave: &Xsave) -> Result<(), Error> {
+        self.set_xsave2(xsave)
+    }
+
+    /// Returns the full XSAVE area of the vCPU, sized by
+    /// `KVM_CAP_XSAVE2` for the VM. Falls back to `KVM_GET_XSAVE` if
+    /// `KVM_CAP_XSAVE2` is not supported.
+    ///
+    /// ```ignore
+    /// let xsave = vcpu.get_xsave2()?;
+    /// for component in xsave.xstate_bv().components() {
+    ///     println!("{:?} in use", component);
+    /// }
+    /// ```
+    pub fn get_xsave2(&self) -> Result<Xsave, Error> {
+        let mut xsave = Xsave::with_size(self.xsave2_size);
+        let request = if self.xsave2_size > 0 {
+            KVM_GET_XSAVE2
+        } else {
+            KVM_GET_XSAVE
+        };
+        let result = unsafe { ioctl(self.ioctl.as_raw_fd(), request, xsave.as_mut_ptr()) };
+        if result == 0 {
+            return Ok(xsave);
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Restores the full XSAVE area of the vCPU, usually saved with
+    /// `get_xsave2`. The area must be at least as large as the size
+    /// reported by `KVM_CAP_XSAVE2` for the VM.
+    pub fn set_xsave2(&self, xsave: &Xsave) -> Result<(), Error> {
+        // KVM reads as many bytes as it needs for the vCPU state, whatever
+        // the size of the buffer passed in.
+        if xsave.size() < self.xsave2_size.max(size_of::<kvm_xsave>()) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "XSAVE area is smaller than the vCPU state",
+            ));
+        }
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_SET_XSAVE, xsave.as_ptr()) };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Returns the extended control registers of the vCPU, such as XCR0.
+    pub fn get_xcrs(&self) -> Result<kvm_xcrs, Error> {
+        let mut xcrs: kvm_xcrs = Default::default();
+        let result = unsafe { ioctl(self.ioctl.as_raw_fd(), KVM_GET_XCRS, &mut xcrs) };
+        if result == 0 {
+            return Ok(xcrs);
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Sets the extended control registers of the vCPU.
+    pub fn set_xcrs(&self, xcrs: &kvm_xcrs) -> Result<(), Error> {
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_SET_XCRS, xcrs) };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Enables an optional capability for this vCPU only.
+    ///
+    /// ```ignore
+    /// vcpu.enable_cap(VcpuCap::EnforcePvFeatureCpuid(true))?;
+    /// ```
+    pub fn enable_cap(&self, cap: VcpuCap) -> Result<(), Error> {
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_ENABLE_CAP, &cap.to_raw()) };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Checks whether the vCPU supports an attribute, such as
+    /// `KVM_VCPU_TSC_OFFSET` in the `KVM_VCPU_TSC_CTRL` group.
+    pub fn has_attr(&self, group: u32, attr: u64) -> Result<bool, Error> {
+        device::has_attr(self.ioctl.as_raw_fd(), group, attr)
+    }
+
+    /// Reads the value of a vCPU attribute that holds at most 64 bits, such
+    /// as `KVM_VCPU_TSC_OFFSET`. Fails with `ErrorKind::InvalidInput` for
+    /// other attributes, which can be read with `get_attr_raw`.
+    ///
+    /// ```ignore
+    /// let tsc_offset = vcpu.get_attr(KVM_VCPU_TSC_CTRL, KVM_VCPU_TSC_OFFSET as u64)?;
+    /// ```
+    pub fn get_attr(&self, group: u32, attr: u64) -> Result<u64, Error> {
+        device::get_attr(self.ioctl.as_raw_fd(), group, attr, device::VCPU_U64_ATTRS)
+    }
+
+    /// Reads the value of a vCPU attribute into the buffer at `addr`.
+    ///
+    /// # Safety
+    ///
+    /// KVM writes as many bytes to `addr` as the attribute holds. The caller
+    /// must guarantee that `addr` points to a writable buffer large enough
+    /// for the attribute.
+    pub unsafe fn get_attr_raw(&self, group: u32, attr: u64, addr: u64) -> Result<(), Error> {
+        device::get_attr_raw(self.ioctl.as_raw_fd(), group, attr, addr)
+    }
+
+    /// Changes the value of a vCPU attribute that holds at most 64 bits.
+    /// Fails with `ErrorKind::InvalidInput` for other attributes, which can
+    /// be set with `set_attr_raw`.
+    pub fn set_attr(&self, group: u32, attr: u64, value: u64) -> Result<(), Error> {
+        let fd = self.ioctl.as_raw_fd();
+        device::set_attr(fd, group, attr, value, device::VCPU_U64_ATTRS)
+    }
+
+    /// Changes the value of a vCPU attribute to the contents of the buffer
+    /// at `addr`.
+    ///
+    /// # Safety
+    ///
+    /// KVM reads as many bytes from `addr` as the attribute holds. The
+    /// caller must guarantee that `addr` points to a valid value for the
+    /// attribute.
+    pub unsafe fn set_attr_raw(&self, group: u32, attr: u64, addr: u64) -> Result<(), Error> {
+        device::set_attr_raw(self.ioctl.as_raw_fd(), group, attr, addr)
+    }
 }
 
 impl Drop for VirtualCPU {
     fn drop(&mut self) {
-        let result = unsafe { libc::munmap(self.kvm_run as *mut libc::c_void, self.vcpu_map_size) };
+        if !self.dirty_ring.is_null() {
+            let size = VirtualCPU::dirty_ring_size(self.dirty_ring_entries);
+            let result = unsafe { libc::munmap(self.dirty_ring as *mut libc::c_void, size) };
+            if result != 0 {
+                panic!("munmap failed with: {}", unsafe {
+                    *libc::__errno_location()
+                });
+            }
+        }
+        // The thread is about to stop running this virtual CPU, and may
+        // exit, so handles must not signal it any more.
+        *self.run_mapping.thread.lock().unwrap() = None;
+    }
+}
+
+// The kvm_run mapping of a virtual CPU, shared between the VirtualCPU and
+// its handles so it stays mapped until the last of them is dropped.
+struct RunMapping {
+    kvm_run: *mut kvm_run,
+    map_size: usize,
+    // The thread that runs the virtual CPU, or None once it is dropped.
+    thread: Mutex<Option<libc::pthread_t>>,
+}
+
+// Safe because other threads only access the mapping through the atomic
+// returned by `immediate_exit`. The VirtualCPU itself reads and writes
+// `immediate_exit` only through the same atomic, and only hands out a
+// mutable reference to the whole kvm_run structure while no handles exist.
+unsafe impl Send for RunMapping {}
+unsafe impl Sync for RunMapping {}
+
+impl RunMapping {
+    // The offset of `immediate_exit` in kvm_run, after the one byte of
+    // `request_interrupt_window`.
+    const IMMEDIATE_EXIT_OFFSET: usize = 1;
+
+    fn immediate_exit(&self) -> &AtomicU8 {
+        unsafe {
+            let address = (self.kvm_run as *mut u8).add(RunMapping::IMMEDIATE_EXIT_OFFSET);
+            &*(address as *const AtomicU8)
+        }
+    }
+}
+
+impl Drop for RunMapping {
+    fn drop(&mut self) {
+        let result = unsafe { libc::munmap(self.kvm_run as *mut libc::c_void, self.map_size) };
         if result != 0 {
             panic!("munmap failed with: {}", unsafe {
                 *libc::__errno_location()
@@ -229,3 +952,57 @@ impl Drop for VirtualCPU {
         }
     }
 }
+
+static KICK_HANDLER: Once = Once::new();
+
+extern "C" fn handle_kick(_signal: libc::c_int) {}
+
+// Installs an empty handler for the kick signal, so it interrupts KVM_RUN
+// instead of terminating the process. SA_RESTART is left out so the run
+// returns EINTR.
+fn install_kick_handler() {
+    KICK_HANDLER.call_once(|| unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handle_kick as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+        let result = libc::sigaction(VcpuHandle::signal(), &action, std::ptr::null_mut());
+        if result != 0 {
+            panic!("sigaction failed with: {}", *libc::__errno_location());
+        }
+    });
+}
+
+/// A handle for stopping a running `VirtualCPU` from another thread, as
+/// returned by `VirtualCPU::handle`. Handles can be cloned and sent to any
+/// thread, and stay valid after the virtual CPU is dropped.
+#[derive(Clone)]
+pub struct VcpuHandle {
+    run_mapping: Arc<RunMapping>,
+}
+
+impl VcpuHandle {
+    /// Returns the real-time signal that `kick` sends to the virtual CPU
+    /// thread. Creating a handle installs an empty handler for it, so the
+    /// application must not use this signal for anything else.
+    pub fn signal() -> libc::c_int {
+        libc::SIGRTMIN()
+    }
+
+    /// Makes the virtual CPU return from its current or next run with
+    /// `VcpuExit::Interrupted`. The run is stopped by setting
+    /// `immediate_exit` in `kvm_run`, and sending `signal` to the virtual
+    /// CPU thread in case it is already in the guest.
+    pub fn kick(&self) -> Result<(), Error> {
+        self.run_mapping.immediate_exit().store(1, Ordering::SeqCst);
+
+        // The lock keeps the thread from exiting while it is signalled.
+        let thread = self.run_mapping.thread.lock().unwrap();
+        if let Some(thread) = *thread {
+            let result = unsafe { libc::pthread_kill(thread, VcpuHandle::signal()) };
+            if result != 0 {
+                return Err(Error::from_raw_os_error(result));
+            }
+        }
+        Ok(())
+    }
+}
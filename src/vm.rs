@@ -6,26 +6,46 @@
 
 use libc;
 use std::fs::File;
-use std::io::Error;
+use std::io::{Error, ErrorKind};
+use std::mem::size_of;
 use std::os::raw::c_void;
 use std::os::unix::io::{AsRawFd, FromRawFd};
 
+use cap::{Capabilities, Capability, VmCap};
+use device::{Device, DeviceType};
+use eventfd::EventFd;
+use irq::{IrqChip, IrqChipState, IrqRoutingTable, IrqStatus};
 use linux::kvm_bindings::*;
 use linux::kvm_ioctl::*;
-use mem::MemorySlot;
+use mem::{DirtyLog, MemorySlot, PAGE_SIZE};
+use system::KVMSystem;
+use utils::KVMIrqRoutingWrapper;
 use vcpu::*;
 
+/// The guest address watched by an ioeventfd.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IoEventAddress {
+    /// A port I/O address.
+    Pio(u64),
+    /// A guest physical address for memory mapped I/O.
+    Mmio(u64),
+}
+
 /// The VirtualMachine module handles KVM virtual machine operations.
 /// It owns the filehandle for these operations.
 pub struct VirtualMachine {
     ioctl: File,
+    dirty_ring_entries: u32,
 }
 
 impl VirtualMachine {
     /// Creates a new `VirtualMachine` from an existing filehandle for
     /// virtual machine operations.
     pub fn from_file(handle: File) -> Self {
-        VirtualMachine { ioctl: handle }
+        VirtualMachine {
+            ioctl: handle,
+            dirty_ring_entries: 0,
+        }
     }
 
     /// Opens a filehandle for virtual CPU operations, and returns a
@@ -41,7 +61,22 @@ impl VirtualMachine {
     ///     let vcpu = vm.create_vcpu().expect("failed to create VCPU");
 
     pub fn create_vcpu(&self) -> Result<VirtualCPU, Error> {
-        let raw_fd = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_CREATE_VCPU, 0) };
+        self.create_vcpu_with_id(0)
+    }
+
+    /// Opens a filehandle for the virtual CPU with the APIC ID `id`, for
+    /// guests with more than one CPU. The CPU with ID 0 is the bootstrap
+    /// processor.
+    ///
+    ///     # use libkvm::system::*;
+    ///     # let system = KVMSystem::new().expect("failed to connect to KVM");
+    ///     # let vm = system.create_vm().expect("failed to create VM");
+    ///     let bsp = vm.create_vcpu_with_id(0).expect("failed to create VCPU");
+    ///     let ap = vm.create_vcpu_with_id(1).expect("failed to create VCPU");
+
+    pub fn create_vcpu_with_id(&self, id: u32) -> Result<VirtualCPU, Error> {
+        let raw_fd =
+            unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_CREATE_VCPU, id as libc::c_ulong) };
         if raw_fd < 0 {
             return Err(Error::last_os_error());
         }
@@ -49,7 +84,13 @@ impl VirtualMachine {
 
         // Return value is safe because raw file descriptor result is checked
         // and ownership of File struct is consumed by VirtualCPU struct.
-        let vcpu = VirtualCPU::from_file(safe_handle)?;
+        let config = VcpuConfig {
+            dirty_ring_entries: self.dirty_ring_entries,
+            xsave2_size: self.check_extension(Capability::Xsave2)? as usize,
+            user_nmi: self.check_extension(Capability::UserNmi)? > 0,
+            smm: Some(self.check_extension(Capability::X86Smm)? > 0),
+        };
+        let vcpu = VirtualCPU::from_file_with_config(safe_handle, config)?;
         Ok(vcpu)
     }
 
@@ -85,6 +126,575 @@ impl VirtualMachine {
         }
     }
 
+    /// Fetches the dirty page bitmap for a memory slot that was registered
+    /// with the `KVM_MEM_LOG_DIRTY_PAGES` flag. Fetching the bitmap resets
+    /// it in KVM, so each call returns the pages written since the previous
+    /// call.
+    ///
+    /// ```ignore
+    /// let log = vm.get_dirty_log(&slot)?;
+    /// for page in log.dirty_pages() {
+    ///     copy_page(&slot, page);
+    /// }
+    /// ```
+
+    pub fn get_dirty_log(&self, slot: &MemorySlot) -> Result<DirtyLog, Error> {
+        let mut log = DirtyLog::new(slot);
+        let mut dirty_log = kvm_dirty_log {
+            slot: slot.slot_id(),
+            ..Default::default()
+        };
+        dirty_log.__bindgen_anon_1.dirty_bitmap = log.as_mut_slice().as_mut_ptr() as *mut c_void;
+
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_GET_DIRTY_LOG, &dirty_log) };
+        if result == 0 {
+            return Ok(log);
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Enables manual protection of dirty pages for the VM. Once enabled,
+    /// `get_dirty_log` no longer write-protects the pages it reports as
+    /// dirty, and pages must be explicitly re-protected with
+    /// `clear_dirty_log` after they are copied. If `initially_set` is true,
+    /// memory slots registered afterwards start with every page marked
+    /// dirty, which avoids write-protecting the whole slot up front.
+    ///
+    /// ```ignore
+    /// vm.enable_manual_dirty_log_protect(false)?;
+    /// ```
+
+    pub fn enable_manual_dirty_log_protect(&self, initially_set: bool) -> Result<(), Error> {
+        let mut flags = KVM_DIRTY_LOG_MANUAL_PROTECT_ENABLE as u64;
+        if initially_set {
+            flags |= KVM_DIRTY_LOG_INITIALLY_SET as u64;
+        }
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_MANUAL_DIRTY_LOG_PROTECT2,
+            ..Default::default()
+        };
+        cap.args[0] = flags;
+        self.enable_cap_raw(&cap)
+    }
+
+    /// Clears the dirty bits for `num_pages` pages of a memory slot,
+    /// starting at page `first_page`, and write-protects the pages again,
+    /// so further writes by the guest are logged. Only pages that are set
+    /// in the `log` bitmap are cleared, which is normally the bitmap last
+    /// returned by `get_dirty_log` for the slot. Requires
+    /// `enable_manual_dirty_log_protect`.
+    ///
+    /// The `log` must cover the same slot. The `first_page` must be a
+    /// multiple of 64, and `num_pages` must be a multiple of 64 unless the
+    /// range extends to the end of the slot.
+    ///
+    /// ```ignore
+    /// let log = vm.get_dirty_log(&slot)?;
+    /// copy_pages(&slot, 0, 512, &log);
+    /// vm.clear_dirty_log(&slot, 0, 512, &log)?;
+    /// ```
+
+    pub fn clear_dirty_log(
+        &self,
+        slot: &MemorySlot,
+        first_page: usize,
+        num_pages: usize,
+        log: &DirtyLog,
+    ) -> Result<(), Error> {
+        const PAGES_PER_WORD: usize = 64;
+        let slot_pages = slot.memory_size().div_ceil(PAGE_SIZE);
+        if log.slot_id() != slot.slot_id() || log.num_pages() != slot_pages {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "dirty log does not belong to the memory slot",
+            ));
+        }
+        let end = first_page.checked_add(num_pages);
+        let valid_range = match end {
+            Some(end) => {
+                end <= log.num_pages()
+                    && first_page.is_multiple_of(PAGES_PER_WORD)
+                    && (num_pages.is_multiple_of(PAGES_PER_WORD) || end == log.num_pages())
+            }
+            None => false,
+        };
+        if !valid_range {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "invalid page range for clearing dirty log",
+            ));
+        }
+
+        let mut clear_log = kvm_clear_dirty_log {
+            slot: slot.slot_id(),
+            num_pages: num_pages as u32,
+            first_page: first_page as u64,
+            ..Default::default()
+        };
+        // KVM only reads the bitmap, starting from the bit for first_page.
+        let bitmap = &log.as_slice()[first_page / PAGES_PER_WORD..];
+        clear_log.__bindgen_anon_1.dirty_bitmap = bitmap.as_ptr() as *mut c_void;
+
+        let result =
+            unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_CLEAR_DIRTY_LOG, &clear_log) };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Enables the per virtual CPU dirty ring for the VM, with the given
+    /// number of entries in each ring. The number of entries must be a power
+    /// of two, and the ring size in bytes (16 bytes per entry) must not
+    /// exceed the maximum reported for `KVM_CAP_DIRTY_LOG_RING`. The dirty
+    /// ring must be enabled before any virtual CPUs are created, and
+    /// replaces `get_dirty_log` for memory slots registered with the
+    /// `KVM_MEM_LOG_DIRTY_PAGES` flag.
+    ///
+    /// ```ignore
+    /// vm.enable_dirty_log_ring(4096)?;
+    /// let mut vcpu = vm.create_vcpu()?;
+    /// ```
+
+    pub fn enable_dirty_log_ring(&mut self, entries: u32) -> Result<(), Error> {
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_DIRTY_LOG_RING,
+            ..Default::default()
+        };
+        cap.args[0] = entries as u64 * size_of::<kvm_dirty_gfn>() as u64;
+        self.enable_cap_raw(&cap)?;
+        self.dirty_ring_entries = entries;
+        Ok(())
+    }
+
+    /// Recycles the dirty ring entries of all virtual CPUs that were
+    /// harvested with `VirtualCPU::harvest_dirty_ring`, and write-protects
+    /// the corresponding pages again. Returns the number of entries reset.
+
+    pub fn reset_dirty_rings(&self) -> Result<u32, Error> {
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_RESET_DIRTY_RINGS) };
+        if result >= 0 {
+            return Ok(result as u32);
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Registers an eventfd to be signalled when the guest writes to an
+    /// address, instead of exiting to userspace. The `len` is the size of
+    /// the write in bytes (1, 2, 4 or 8), or 0 to match writes of any
+    /// length. If `datamatch` is given, only writes of that value signal the
+    /// eventfd, and `len` must not be 0.
+    ///
+    /// ```ignore
+    /// let notify = EventFd::new(false)?;
+    /// vm.register_ioeventfd(&notify, IoEventAddress::Mmio(0xd000_0050), 4, Some(0))?;
+    /// ```
+
+    pub fn register_ioeventfd(
+        &self,
+        eventfd: &EventFd,
+        address: IoEventAddress,
+        len: u32,
+        datamatch: Option<u64>,
+    ) -> Result<(), Error> {
+        self.ioeventfd(eventfd, address, len, datamatch, 0)
+    }
+
+    /// Removes an eventfd registered with `register_ioeventfd`. The
+    /// arguments must be the same as when it was registered.
+
+    pub fn unregister_ioeventfd(
+        &self,
+        eventfd: &EventFd,
+        address: IoEventAddress,
+        len: u32,
+        datamatch: Option<u64>,
+    ) -> Result<(), Error> {
+        self.ioeventfd(
+            eventfd,
+            address,
+            len,
+            datamatch,
+            1 << kvm_ioeventfd_flag_nr_deassign,
+        )
+    }
+
+    fn ioeventfd(
+        &self,
+        eventfd: &EventFd,
+        address: IoEventAddress,
+        len: u32,
+        datamatch: Option<u64>,
+        mut flags: u32,
+    ) -> Result<(), Error> {
+        match len {
+            0 | 1 | 2 | 4 | 8 => {}
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "ioeventfd length must be 0, 1, 2, 4 or 8",
+                ));
+            }
+        }
+        if len == 0 && datamatch.is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "ioeventfd datamatch requires a length",
+            ));
+        }
+
+        let addr = match address {
+            IoEventAddress::Pio(addr) => {
+                flags |= 1 << kvm_ioeventfd_flag_nr_pio;
+                addr
+            }
+            IoEventAddress::Mmio(addr) => addr,
+        };
+        if datamatch.is_some() {
+            flags |= 1 << kvm_ioeventfd_flag_nr_datamatch;
+        }
+        let ioeventfd = kvm_ioeventfd {
+            datamatch: datamatch.unwrap_or(0),
+            addr,
+            len,
+            fd: eventfd.as_raw_fd(),
+            flags,
+            ..Default::default()
+        };
+
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_IOEVENTFD, &ioeventfd) };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Replaces the GSI routing table for the VM, which decides where
+    /// interrupts raised with `register_irqfd` and the irq line operations
+    /// are delivered.
+    ///
+    /// ```ignore
+    /// let mut routing = IrqRoutingTable::legacy();
+    /// routing.add_msi(24, 0xfee0_0000, 0x4041, None);
+    /// vm.set_gsi_routing(&routing)?;
+    /// ```
+
+    pub fn set_gsi_routing(&self, table: &IrqRoutingTable) -> Result<(), Error> {
+        let routing = KVMIrqRoutingWrapper::from_routing_entries(table.entries());
+        let result = unsafe {
+            libc::ioctl(
+                self.ioctl.as_raw_fd(),
+                KVM_SET_GSI_ROUTING,
+                routing.as_ptr(),
+            )
+        };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Fetches the state of one of the in-kernel interrupt controller chips,
+    /// see `create_irq_chip`.
+    ///
+    /// ```ignore
+    /// let ioapic = vm.get_irqchip(IrqChip::Ioapic)?;
+    /// ```
+
+    pub fn get_irqchip(&self, chip: IrqChip) -> Result<IrqChipState, Error> {
+        let mut irqchip = kvm_irqchip {
+            chip_id: chip.chip_id(),
+            ..Default::default()
+        };
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_GET_IRQCHIP, &mut irqchip) };
+        if result != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        // Safe because KVM fills in the union member for the requested chip.
+        let state = unsafe {
+            match chip {
+                IrqChip::PicMaster => IrqChipState::PicMaster(irqchip.chip.pic),
+                IrqChip::PicSlave => IrqChipState::PicSlave(irqchip.chip.pic),
+                IrqChip::Ioapic => IrqChipState::Ioapic(irqchip.chip.ioapic),
+            }
+        };
+        Ok(state)
+    }
+
+    /// Restores the state of one of the in-kernel interrupt controller
+    /// chips, as returned by `get_irqchip`.
+
+    pub fn set_irqchip(&self, state: &IrqChipState) -> Result<(), Error> {
+        let mut irqchip = kvm_irqchip {
+            chip_id: state.chip().chip_id(),
+            ..Default::default()
+        };
+        match *state {
+            IrqChipState::PicMaster(pic) | IrqChipState::PicSlave(pic) => irqchip.chip.pic = pic,
+            IrqChipState::Ioapic(ioapic) => irqchip.chip.ioapic = ioapic,
+        }
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_SET_IRQCHIP, &irqchip) };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Sets the level of an interrupt line on the in-kernel interrupt
+    /// controllers, see `create_irq_chip`. The `irq` is a GSI, which is
+    /// delivered according to the GSI routing table. Edge triggered
+    /// interrupts are raised by setting the level high and then low.
+    ///
+    /// ```ignore
+    /// vm.set_irq_line(4, true)?;
+    /// vm.set_irq_line(4, false)?;
+    /// ```
+
+    pub fn set_irq_line(&self, irq: u32, level: bool) -> Result<(), Error> {
+        let irq_level = VirtualMachine::irq_level(irq, level);
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_IRQ_LINE, &irq_level) };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Sets the level of an interrupt line like `set_irq_line`, and returns
+    /// whether the interrupt was delivered, coalesced with an interrupt that
+    /// is still pending, or blocked because the guest masked it.
+
+    pub fn set_irq_line_status(&self, irq: u32, level: bool) -> Result<IrqStatus, Error> {
+        let mut irq_level = VirtualMachine::irq_level(irq, level);
+        let result =
+            unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_IRQ_LINE_STATUS, &mut irq_level) };
+        if result != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let status = unsafe { irq_level.__bindgen_anon_1.status };
+        if status > 0 {
+            return Ok(IrqStatus::Delivered(status as u32));
+        } else if status == 0 {
+            return Ok(IrqStatus::Coalesced);
+        } else {
+            return Ok(IrqStatus::Blocked);
+        }
+    }
+
+    fn irq_level(irq: u32, level: bool) -> kvm_irq_level {
+        let mut irq_level = kvm_irq_level {
+            level: level as u32,
+            ..Default::default()
+        };
+        irq_level.__bindgen_anon_1.irq = irq;
+        irq_level
+    }
+
+    /// Injects an MSI message directly into the guest, without a GSI
+    /// routing entry. The `devid` identifies the requester, and is only
+    /// needed on platforms that require it. Returns whether the interrupt
+    /// was delivered, or blocked because the guest masked it or no virtual
+    /// CPU accepts it. Requires an in-kernel interrupt controller, see
+    /// `create_irq_chip`.
+    ///
+    /// KVM reports an MSI that is coalesced with a pending interrupt the
+    /// same way as a masked one, so `IrqStatus::Blocked` covers both cases,
+    /// and `IrqStatus::Coalesced` is never returned.
+    ///
+    /// ```ignore
+    /// match vm.signal_msi(0xfee0_0000, 0x4041, None)? {
+    ///     IrqStatus::Blocked => pending = true,
+    ///     _ => {}
+    /// }
+    /// ```
+
+    pub fn signal_msi(
+        &self,
+        address: u64,
+        data: u32,
+        devid: Option<u32>,
+    ) -> Result<IrqStatus, Error> {
+        let mut msi = kvm_msi {
+            address_lo: address as u32,
+            address_hi: (address >> 32) as u32,
+            data,
+            ..Default::default()
+        };
+        if let Some(devid) = devid {
+            msi.flags = KVM_MSI_VALID_DEVID;
+            msi.devid = devid;
+        }
+
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_SIGNAL_MSI, &msi) };
+        if result > 0 {
+            return Ok(IrqStatus::Delivered(result as u32));
+        } else if result == 0 {
+            return Ok(IrqStatus::Blocked);
+        }
+        // KVM returns -1 when the message has no destination, which
+        // userspace sees as EPERM.
+        let error = Error::last_os_error();
+        if error.raw_os_error() == Some(libc::EPERM) {
+            return Ok(IrqStatus::Blocked);
+        } else {
+            return Err(error);
+        }
+    }
+
+    /// Registers an eventfd that injects an interrupt on the given GSI when
+    /// it is signalled, so device threads can raise interrupts without
+    /// going through the virtual CPU thread. Requires an in-kernel
+    /// interrupt controller, see `create_irq_chip`.
+    ///
+    /// Without a `resample` eventfd, the interrupt is edge triggered. With
+    /// one, the interrupt is level triggered: the line stays asserted until
+    /// the guest acknowledges it, at which point KVM deasserts it and
+    /// signals `resample`, so the device can check whether it needs to
+    /// raise the interrupt again.
+    ///
+    /// ```ignore
+    /// let irq = EventFd::new(false)?;
+    /// let resample = EventFd::new(false)?;
+    /// vm.register_irqfd(10, &irq, Some(&resample))?;
+    /// ```
+
+    pub fn register_irqfd(
+        &self,
+        gsi: u32,
+        eventfd: &EventFd,
+        resample: Option<&EventFd>,
+    ) -> Result<(), Error> {
+        let mut irqfd = kvm_irqfd {
+            fd: eventfd.as_raw_fd() as u32,
+            gsi,
+            ..Default::default()
+        };
+        if let Some(resample) = resample {
+            irqfd.flags = KVM_IRQFD_FLAG_RESAMPLE;
+            irqfd.resamplefd = resample.as_raw_fd() as u32;
+        }
+        self.irqfd(&irqfd)
+    }
+
+    /// Removes an eventfd registered with `register_irqfd` for the GSI.
+
+    pub fn unregister_irqfd(&self, gsi: u32, eventfd: &EventFd) -> Result<(), Error> {
+        let irqfd = kvm_irqfd {
+            fd: eventfd.as_raw_fd() as u32,
+            gsi,
+            flags: KVM_IRQFD_FLAG_DEASSIGN,
+            ..Default::default()
+        };
+        self.irqfd(&irqfd)
+    }
+
+    fn irqfd(&self, irqfd: &kvm_irqfd) -> Result<(), Error> {
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_IRQFD, irqfd) };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Check whether this VM supports a capability. Some capabilities
+    /// depend on the type or configuration of the VM, so the value may
+    /// differ from `KVMSystem::check_extension`. If KVM does not support
+    /// `KVM_CAP_CHECK_EXTENSION_VM`, the capability is checked on the KVM
+    /// system filehandle instead.
+    ///
+    ///     # use libkvm::cap::*;
+    ///     # use libkvm::system::*;
+    ///     # let system = KVMSystem::new().expect("failed to connect to KVM");
+    ///     # let vm = system.create_vm().expect("failed to create VM");
+    ///     let result = vm.check_extension(Capability::NrMemslots);
+
+    pub fn check_extension(&self, capability: Capability) -> Result<i32, Error> {
+        match self.check_vm_extension(capability) {
+            Err(ref error) if VirtualMachine::is_unsupported_ioctl(error) => {
+                KVMSystem::new()?.check_extension(capability)
+            }
+            result => result,
+        }
+    }
+
+    /// Check every capability listed in `Capability` for this VM, and
+    /// return a snapshot of their values.
+    ///
+    ///     # use libkvm::system::*;
+    ///     # let system = KVMSystem::new().expect("failed to connect to KVM");
+    ///     # let vm = system.create_vm().expect("failed to create VM");
+    ///     let caps = vm.capabilities().expect("failed to check capabilities");
+    ///     let nr_memslots = caps.nr_memslots();
+
+    pub fn capabilities(&self) -> Result<Capabilities, Error> {
+        match self.check_vm_extension(Capability::CheckExtensionVm) {
+            Err(ref error) if VirtualMachine::is_unsupported_ioctl(error) => {
+                KVMSystem::new()?.capabilities()
+            }
+            Err(error) => Err(error),
+            Ok(_) => Capabilities::probe(|cap| self.check_vm_extension(cap)),
+        }
+    }
+
+    fn check_vm_extension(&self, capability: Capability) -> Result<i32, Error> {
+        let result = unsafe {
+            libc::ioctl(
+                self.ioctl.as_raw_fd(),
+                KVM_CHECK_EXTENSION,
+                capability.to_raw(),
+            )
+        };
+        if result > -1 {
+            return Ok(result);
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    // Kernels without KVM_CAP_CHECK_EXTENSION_VM reject KVM_CHECK_EXTENSION
+    // on the VM filehandle with one of these errors.
+    fn is_unsupported_ioctl(error: &Error) -> bool {
+        matches!(
+            error.raw_os_error(),
+            Some(libc::EINVAL) | Some(libc::ENOTTY)
+        )
+    }
+
+    /// Enables an optional capability for the VM. The arguments of the
+    /// capability are checked first, failing with `ErrorKind::InvalidInput`
+    /// if they are out of range. Several capabilities, such as
+    /// `VmCap::SplitIrqchip`, must be enabled before any virtual CPUs are
+    /// created.
+    ///
+    ///     # use libkvm::cap::*;
+    ///     # use libkvm::system::*;
+    ///     # let system = KVMSystem::new().expect("failed to connect to KVM");
+    ///     # let vm = system.create_vm().expect("failed to create VM");
+    ///     vm.enable_cap(VmCap::SplitIrqchip { ioapic_pins: 24 })
+    ///         .expect("failed to enable split irqchip");
+
+    pub fn enable_cap(&self, cap: VmCap) -> Result<(), Error> {
+        self.enable_cap_raw(&cap.to_raw()?)
+    }
+
+    fn enable_cap_raw(&self, cap: &kvm_enable_cap) -> Result<(), Error> {
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_ENABLE_CAP, cap) };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
     pub fn set_tss_address(&self, tss_address: u32) -> Result<(), Error> {
         let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_SET_TSS_ADDR, tss_address) };
         if result == 0 {
@@ -95,8 +705,70 @@ impl VirtualMachine {
     }
 
     pub fn create_pit2(&self) -> Result<(), Error> {
-        let pit_config = kvm_pit_config::default();
-        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_CREATE_PIT2, &pit_config) };
+        self.create_pit2_with_config(&kvm_pit_config::default())
+    }
+
+    /// Creates an in-kernel PIT with the given configuration. Setting the
+    /// `KVM_PIT_SPEAKER_DUMMY` flag makes KVM emulate a dummy PC speaker
+    /// port, so guests probing it do not exit to userspace.
+    ///
+    /// ```ignore
+    /// let config = kvm_pit_config {
+    ///     flags: KVM_PIT_SPEAKER_DUMMY,
+    ///     ..Default::default()
+    /// };
+    /// vm.create_pit2_with_config(&config)?;
+    /// ```
+
+    pub fn create_pit2_with_config(&self, pit_config: &kvm_pit_config) -> Result<(), Error> {
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_CREATE_PIT2, pit_config) };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Fetches the state of the in-kernel PIT, for saving a snapshot.
+
+    pub fn get_pit2(&self) -> Result<kvm_pit_state2, Error> {
+        let mut pit_state: kvm_pit_state2 = Default::default();
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_GET_PIT2, &mut pit_state) };
+        if result == 0 {
+            return Ok(pit_state);
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Restores the state of the in-kernel PIT, as returned by `get_pit2`.
+
+    pub fn set_pit2(&self, pit_state: &kvm_pit_state2) -> Result<(), Error> {
+        let result = unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_SET_PIT2, pit_state) };
+        if result == 0 {
+            return Ok(());
+        } else {
+            return Err(Error::last_os_error());
+        }
+    }
+
+    /// Controls whether the in-kernel PIT reinjects timer interrupts that
+    /// the guest missed while it was not running. Reinjection is enabled by
+    /// default, which keeps time for guests that count ticks, but can
+    /// cause bursts of interrupts after the VM is paused.
+
+    pub fn set_reinject_control(&self, reinject: bool) -> Result<(), Error> {
+        let reinject_control = kvm_reinject_control {
+            pit_reinject: reinject as u8,
+            ..Default::default()
+        };
+        let result = unsafe {
+            libc::ioctl(
+                self.ioctl.as_raw_fd(),
+                KVM_REINJECT_CONTROL,
+                &reinject_control,
+            )
+        };
         if result == 0 {
             return Ok(());
         } else {
@@ -112,4 +784,61 @@ impl VirtualMachine {
             return Err(Error::last_os_error());
         }
     }
+
+    /// Creates an in-kernel device of the given type, and returns a
+    /// `Device` for setting and reading its attributes. Fails with ENODEV
+    /// if KVM does not support the device type.
+    ///
+    ///     # use libkvm::device::*;
+    ///     # use libkvm::system::*;
+    ///     # let system = KVMSystem::new().expect("failed to connect to KVM");
+    ///     # let vm = system.create_vm().expect("failed to create VM");
+    ///     let vfio = vm.create_device(DeviceType::Vfio).expect("failed to create device");
+
+    pub fn create_device(&self, device_type: DeviceType) -> Result<Device, Error> {
+        let mut create_device = kvm_create_device {
+            type_: device_type.to_raw(),
+            ..Default::default()
+        };
+        let result = unsafe {
+            libc::ioctl(
+                self.ioctl.as_raw_fd(),
+                KVM_CREATE_DEVICE,
+                &mut create_device,
+            )
+        };
+        if result != 0 {
+            return Err(Error::last_os_error());
+        }
+        let safe_handle = unsafe { File::from_raw_fd(create_device.fd as i32) };
+        Ok(Device::from_file(safe_handle, device_type))
+    }
+
+    /// Checks whether KVM can create an in-kernel device of the given type
+    /// for this VM, without creating it.
+    ///
+    ///     # use libkvm::device::*;
+    ///     # use libkvm::system::*;
+    ///     # let system = KVMSystem::new().expect("failed to connect to KVM");
+    ///     # let vm = system.create_vm().expect("failed to create VM");
+    ///     let result = vm.supports_device(DeviceType::Vfio);
+
+    pub fn supports_device(&self, device_type: DeviceType) -> Result<bool, Error> {
+        let create_device = kvm_create_device {
+            type_: device_type.to_raw(),
+            flags: KVM_CREATE_DEVICE_TEST,
+            ..Default::default()
+        };
+        let result =
+            unsafe { libc::ioctl(self.ioctl.as_raw_fd(), KVM_CREATE_DEVICE, &create_device) };
+        if result == 0 {
+            return Ok(true);
+        }
+        let error = Error::last_os_error();
+        if error.raw_os_error() == Some(libc::ENODEV) {
+            return Ok(false);
+        } else {
+            return Err(error);
+        }
+    }
 }
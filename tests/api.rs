@@ -1,12 +1,21 @@
 extern crate libc;
 extern crate libkvm;
 
+use libkvm::cap::*;
+use libkvm::cpu::*;
+use libkvm::device::DeviceType;
+use libkvm::eventfd::EventFd;
+use libkvm::exit::*;
+use libkvm::irq::{IrqChip, IrqRoutingTable, IrqStatus};
+use libkvm::linux::kvm_bindings::*;
 use libkvm::system::*;
+use libkvm::vcpu::{VcpuHandle, VirtualCPU};
+use libkvm::vm::{IoEventAddress, VirtualMachine};
 
-use std::io::Error;
+use std::io::{Error, ErrorKind};
 use std::ptr::null_mut;
 
-use libkvm::mem::MemorySlot;
+use libkvm::mem::{DirtyLog, MemorySlot};
 
 pub struct MockSlot {
     id: u32,
@@ -89,3 +98,913 @@ fn create_vm() {
     let running = vcpu.run().expect("failed to run VCPU");
     assert!(running)
 }
+
+fn setup_real_mode_guest(vm: &VirtualMachine, slot: &MockSlot, code: &[u8]) -> VirtualCPU {
+    const CODE_ADDR: u64 = 0x1000;
+
+    vm.set_user_memory_region(slot)
+        .expect("failed to set user memory region");
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            code.as_ptr(),
+            (slot.host_address() + CODE_ADDR) as *mut u8,
+            code.len(),
+        );
+    }
+
+    let vcpu = vm.create_vcpu().expect("failed to create VCPU");
+    let mut sregs = vcpu.get_kvm_sregs().expect("failed to get sregs");
+    sregs.cs.base = 0;
+    sregs.cs.selector = 0;
+    vcpu.set_kvm_sregs(&sregs).expect("failed to set sregs");
+
+    let mut regs = vcpu.get_kvm_regs().expect("failed to get regs");
+    regs.rip = CODE_ADDR;
+    regs.rflags = 2;
+    vcpu.set_kvm_regs(&regs).expect("failed to set regs");
+    vcpu
+}
+
+#[test]
+fn run_exit_reasons() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    let slot = MockSlot::new(0x10000).expect("failed to create memory region");
+
+    // mov al, 0x42; out 0x10, al; hlt
+    let code = [0xb0, 0x42, 0xe6, 0x10, 0xf4];
+    let mut vcpu = setup_real_mode_guest(&vm, &slot, &code);
+
+    match vcpu.run_exit().expect("failed to run VCPU") {
+        VcpuExit::Io(io) => {
+            assert_eq!(io.direction(), IoDirection::Out);
+            assert_eq!(io.port(), 0x10);
+            assert_eq!(io.size(), 1);
+            assert_eq!(io.count(), 1);
+        }
+        exit => panic!("unexpected exit: {:?}", exit),
+    }
+    match vcpu.run_exit().expect("failed to run VCPU") {
+        VcpuExit::Hlt => {}
+        exit => panic!("unexpected exit: {:?}", exit),
+    }
+}
+
+#[test]
+fn io_exit_data() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    let slot = MockSlot::new(0x10000).expect("failed to create memory region");
+
+    let code = [
+        0xbe, 0x10, 0x10, // mov si, 0x1010
+        0xb9, 0x03, 0x00, // mov cx, 3
+        0xba, 0x10, 0x00, // mov dx, 0x10
+        0xf3, 0x6e, // rep outsb
+        0xe4, 0x20, // in al, 0x20
+        0xe6, 0x21, // out 0x21, al
+        0xf4, // hlt
+        b'a', b'b', b'c',
+    ];
+    let mut vcpu = setup_real_mode_guest(&vm, &slot, &code);
+
+    let mut output = Vec::new();
+    loop {
+        vcpu.run_exit().expect("failed to run VCPU");
+        let mut io = vcpu.io_exit().expect("expected an I/O exit");
+        if io.port() != 0x10 {
+            break;
+        }
+        assert_eq!(io.direction(), IoDirection::Out);
+        assert_eq!(io.data().len(), io.count());
+        output.extend(io.iterations_mut().map(|byte| byte[0]));
+    }
+    assert_eq!(output, b"abc");
+
+    {
+        let mut io = vcpu.io_exit().expect("expected an I/O exit");
+        assert_eq!(io.direction(), IoDirection::In);
+        assert_eq!(io.port(), 0x20);
+        io.data_mut().copy_from_slice(&[0x5a]);
+    }
+
+    vcpu.run_exit().expect("failed to run VCPU");
+    let io = vcpu.io_exit().expect("expected an I/O exit");
+    assert_eq!(io.port(), 0x21);
+    assert_eq!(io.data(), &[0x5a]);
+}
+
+#[test]
+fn mmio_exit_data() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    let slot = MockSlot::new(0x10000).expect("failed to create memory region");
+
+    let code = [
+        0xbb, 0x00, 0x20, // mov bx, 0x2000
+        0x8e, 0xdb, // mov ds, bx
+        0xa1, 0x00, 0x00, // mov ax, [0]
+        0xa3, 0x02, 0x00, // mov [2], ax
+        0xf4, // hlt
+    ];
+    let mut vcpu = setup_real_mode_guest(&vm, &slot, &code);
+
+    vcpu.run_exit().expect("failed to run VCPU");
+    {
+        let mut mmio = vcpu.mmio_exit().expect("expected an MMIO exit");
+        assert_eq!(mmio.phys_addr(), 0x20000);
+        assert_eq!(mmio.len(), 2);
+        assert!(!mmio.is_write());
+        assert!(mmio.complete_read(&[0x34, 0x12, 0x00, 0x00]).is_err());
+        mmio.complete_read(&[0x34, 0x12])
+            .expect("failed to complete read");
+    }
+
+    vcpu.run_exit().expect("failed to run VCPU");
+    let mut mmio = vcpu.mmio_exit().expect("expected an MMIO exit");
+    assert_eq!(mmio.phys_addr(), 0x20002);
+    assert!(mmio.is_write());
+    assert_eq!(mmio.data(), &[0x34, 0x12]);
+    assert!(mmio.complete_read(&[0x00, 0x00]).is_err());
+}
+
+#[test]
+fn dirty_log() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    let mut slot = MockSlot::new(0x10000).expect("failed to create memory region");
+    slot.flags = KVM_MEM_LOG_DIRTY_PAGES;
+
+    let code = [
+        0xa3, 0x00, 0x30, // mov [0x3000], ax
+        0xa3, 0x04, 0x50, // mov [0x5004], ax
+        0xf4, // hlt
+    ];
+    let mut vcpu = setup_real_mode_guest(&vm, &slot, &code);
+    match vcpu.run_exit().expect("failed to run VCPU") {
+        VcpuExit::Hlt => {}
+        exit => panic!("unexpected exit: {:?}", exit),
+    }
+
+    let log = vm.get_dirty_log(&slot).expect("failed to get dirty log");
+    assert_eq!(log.num_pages(), 16);
+    assert_eq!(log.dirty_pages().collect::<Vec<_>>(), vec![3, 5]);
+    assert_eq!(
+        log.dirty_addresses().collect::<Vec<_>>(),
+        vec![0x3000, 0x5000]
+    );
+
+    let log = vm.get_dirty_log(&slot).expect("failed to get dirty log");
+    assert_eq!(log.count(), 0);
+}
+
+#[test]
+fn clear_dirty_log() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    vm.enable_manual_dirty_log_protect(false)
+        .expect("failed to enable manual dirty log protection");
+    let mut slot = MockSlot::new(0x10000).expect("failed to create memory region");
+    slot.flags = KVM_MEM_LOG_DIRTY_PAGES;
+
+    let code = [
+        0xa3, 0x00, 0x30, // mov [0x3000], ax
+        0xf4, // hlt
+    ];
+    let mut vcpu = setup_real_mode_guest(&vm, &slot, &code);
+    vcpu.run_exit().expect("failed to run VCPU");
+
+    let log = vm.get_dirty_log(&slot).expect("failed to get dirty log");
+    assert_eq!(log.dirty_pages().collect::<Vec<_>>(), vec![3]);
+
+    // Pages stay dirty until they are explicitly cleared.
+    let log = vm.get_dirty_log(&slot).expect("failed to get dirty log");
+    assert_eq!(log.dirty_pages().collect::<Vec<_>>(), vec![3]);
+
+    assert!(vm.clear_dirty_log(&slot, 1, 15, &log).is_err());
+    assert!(vm.clear_dirty_log(&slot, 0, 17, &log).is_err());
+    let mut other = MockSlot::new(0x20000).expect("failed to create memory region");
+    other.id = 1;
+    let other_log = DirtyLog::new(&other);
+    assert!(vm.clear_dirty_log(&slot, 0, 16, &other_log).is_err());
+    vm.clear_dirty_log(&slot, 0, log.num_pages(), &log)
+        .expect("failed to clear dirty log");
+    let log = vm.get_dirty_log(&slot).expect("failed to get dirty log");
+    assert_eq!(log.count(), 0);
+}
+
+#[test]
+fn dirty_ring() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let mut vm = sys.create_vm().expect("failed to create VM");
+    vm.enable_dirty_log_ring(256)
+        .expect("failed to enable dirty ring");
+    let mut slot = MockSlot::new(0x10000).expect("failed to create memory region");
+    slot.flags = KVM_MEM_LOG_DIRTY_PAGES;
+
+    let code = [
+        0xa3, 0x00, 0x30, // mov [0x3000], ax
+        0xa3, 0x04, 0x50, // mov [0x5004], ax
+        0xf4, // hlt
+    ];
+    let mut vcpu = setup_real_mode_guest(&vm, &slot, &code);
+    vcpu.run_exit().expect("failed to run VCPU");
+
+    let mut pages = vcpu
+        .harvest_dirty_ring()
+        .expect("failed to harvest dirty ring");
+    pages.sort();
+    assert_eq!(pages, vec![(0, 3), (0, 5)]);
+    let pages = vcpu
+        .harvest_dirty_ring()
+        .expect("failed to harvest dirty ring");
+    assert!(pages.is_empty());
+    let reset = vm.reset_dirty_rings().expect("failed to reset dirty rings");
+    assert_eq!(reset, 2);
+
+    let vm = sys.create_vm().expect("failed to create VM");
+    let mut vcpu = vm.create_vcpu().expect("failed to create VCPU");
+    let err = vcpu.harvest_dirty_ring().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn ioeventfd() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    let slot = MockSlot::new(0x10000).expect("failed to create memory region");
+
+    let code = [
+        0xb0, 0x01, // mov al, 1
+        0xe6, 0x10, // out 0x10, al
+        0xb0, 0x02, // mov al, 2
+        0xe6, 0x10, // out 0x10, al
+        0xf4, // hlt
+    ];
+    let mut vcpu = setup_real_mode_guest(&vm, &slot, &code);
+
+    let eventfd = EventFd::new(true).expect("failed to create eventfd");
+    let address = IoEventAddress::Pio(0x10);
+    assert!(vm.register_ioeventfd(&eventfd, address, 0, Some(1)).is_err());
+    assert!(vm.register_ioeventfd(&eventfd, address, 3, None).is_err());
+    vm.register_ioeventfd(&eventfd, address, 1, Some(1))
+        .expect("failed to register ioeventfd");
+
+    // The first write matches and is handled in the kernel, the second
+    // does not match and exits to userspace.
+    match vcpu.run_exit().expect("failed to run VCPU") {
+        VcpuExit::Io(io) => assert_eq!(io.data(), &[2]),
+        exit => panic!("unexpected exit: {:?}", exit),
+    }
+    assert_eq!(eventfd.read().expect("failed to read eventfd"), 1);
+
+    vm.unregister_ioeventfd(&eventfd, address, 1, Some(1))
+        .expect("failed to unregister ioeventfd");
+}
+
+#[test]
+fn irqfd() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    vm.create_irq_chip().expect("failed to create IRQ chip");
+
+    let edge = EventFd::new(true).expect("failed to create eventfd");
+    vm.register_irqfd(4, &edge, None)
+        .expect("failed to register irqfd");
+    edge.write(1).expect("failed to signal eventfd");
+
+    let level = EventFd::new(true).expect("failed to create eventfd");
+    let resample = EventFd::new(true).expect("failed to create eventfd");
+    vm.register_irqfd(5, &level, Some(&resample))
+        .expect("failed to register resample irqfd");
+
+    vm.unregister_irqfd(4, &edge)
+        .expect("failed to unregister irqfd");
+    vm.unregister_irqfd(5, &level)
+        .expect("failed to unregister irqfd");
+}
+
+#[test]
+fn gsi_routing() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    vm.create_irq_chip().expect("failed to create IRQ chip");
+
+    let mut routing = IrqRoutingTable::legacy();
+    assert_eq!(routing.len(), 40);
+    routing
+        .add_msi(24, 0xfee0_0000, 0x4041, None)
+        .add_msi(25, 0xfee0_0000, 0x4042, None);
+    vm.set_gsi_routing(&routing)
+        .expect("failed to set GSI routing");
+}
+
+#[test]
+fn signal_msi() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    vm.create_irq_chip().expect("failed to create IRQ chip");
+    let vcpu = vm.create_vcpu().expect("failed to create VCPU");
+
+    // The local APIC is software disabled after reset.
+    let status = vm.signal_msi(0xfee0_0000, 0x41, None)
+        .expect("failed to signal MSI");
+    assert_eq!(status, IrqStatus::Blocked);
+
+    // Set the APIC software enable bit in the spurious interrupt vector
+    // register.
+    let mut lapic = vcpu.get_lapic().expect("failed to get LAPIC");
+    lapic.regs[0xf1] |= 1;
+    vcpu.set_lapic(&lapic).expect("failed to set LAPIC");
+
+    let status = vm.signal_msi(0xfee0_0000, 0x41, None)
+        .expect("failed to signal MSI");
+    assert_eq!(status, IrqStatus::Delivered(1));
+
+    // No virtual CPU has APIC ID 5.
+    let status = vm.signal_msi(0xfee0_5000, 0x41, None)
+        .expect("failed to signal MSI");
+    assert_eq!(status, IrqStatus::Blocked);
+}
+
+#[test]
+fn irq_line() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    vm.create_irq_chip().expect("failed to create IRQ chip");
+    let _vcpu = vm.create_vcpu().expect("failed to create VCPU");
+
+    vm.set_irq_line(4, true).expect("failed to raise IRQ line");
+    vm.set_irq_line(4, false).expect("failed to lower IRQ line");
+
+    match vm.set_irq_line_status(3, true)
+        .expect("failed to raise IRQ line")
+    {
+        IrqStatus::Delivered(_) => {}
+        status => panic!("unexpected IRQ status: {:?}", status),
+    }
+    vm.set_irq_line_status(3, false)
+        .expect("failed to lower IRQ line");
+
+    // The guest has not acknowledged the first interrupt yet.
+    let status = vm.set_irq_line_status(3, true)
+        .expect("failed to raise IRQ line");
+    assert_eq!(status, IrqStatus::Coalesced);
+}
+
+#[test]
+fn irqchip_state() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    vm.create_irq_chip().expect("failed to create IRQ chip");
+
+    let pic = vm.get_irqchip(IrqChip::PicMaster)
+        .expect("failed to get PIC state");
+    assert_eq!(pic.chip(), IrqChip::PicMaster);
+    assert!(pic.ioapic_redirection_entry(0).is_none());
+    vm.set_irqchip(&pic).expect("failed to set PIC state");
+
+    let mut ioapic = vm.get_irqchip(IrqChip::Ioapic)
+        .expect("failed to get IOAPIC state");
+    let mut entry = ioapic
+        .ioapic_redirection_entry(4)
+        .expect("missing redirection entry");
+    assert!(entry.is_masked());
+    assert!(ioapic.ioapic_redirection_entry(24).is_none());
+
+    entry.set_vector(0x34);
+    entry.set_dest_id(1);
+    entry.set_masked(false);
+    ioapic
+        .set_ioapic_redirection_entry(4, entry)
+        .expect("failed to set redirection entry");
+    vm.set_irqchip(&ioapic).expect("failed to set IOAPIC state");
+
+    let ioapic = vm.get_irqchip(IrqChip::Ioapic)
+        .expect("failed to get IOAPIC state");
+    let entry = ioapic
+        .ioapic_redirection_entry(4)
+        .expect("missing redirection entry");
+    assert_eq!(entry.vector(), 0x34);
+    assert_eq!(entry.dest_id(), 1);
+    assert!(!entry.is_masked());
+    assert!(!entry.is_level_triggered());
+}
+
+#[test]
+fn pit_state() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    vm.create_irq_chip().expect("failed to create IRQ chip");
+    let config = kvm_pit_config {
+        flags: KVM_PIT_SPEAKER_DUMMY,
+        ..Default::default()
+    };
+    vm.create_pit2_with_config(&config)
+        .expect("failed to create PIT");
+    vm.set_reinject_control(false)
+        .expect("failed to disable PIT reinjection");
+
+    let mut pit_state = vm.get_pit2().expect("failed to get PIT state");
+    pit_state.channels[0].count = 0x1234;
+    pit_state.channels[0].mode = 2;
+    vm.set_pit2(&pit_state).expect("failed to set PIT state");
+
+    let pit_state = vm.get_pit2().expect("failed to get PIT state");
+    assert_eq!(pit_state.channels[0].count, 0x1234);
+    assert_eq!(pit_state.channels[0].mode, 2);
+}
+
+#[test]
+fn vcpu_events() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    let vcpu = vm.create_vcpu().expect("failed to create vCPU");
+
+    let mut events = vcpu.get_vcpu_events().expect("failed to get vCPU events");
+    assert_eq!(events.exception(), None);
+    assert!(!events.nmi_pending());
+
+    let exception = ExceptionEvent {
+        vector: 13,
+        error_code: Some(0x18),
+        payload: None,
+        injected: true,
+    };
+    events.set_exception(Some(exception));
+    events.set_nmi_pending(true);
+    events.set_interrupt_shadow(InterruptShadow {
+        mov_ss: false,
+        sti: true,
+    });
+    vcpu.set_vcpu_events(&events)
+        .expect("failed to set vCPU events");
+
+    let events = vcpu.get_vcpu_events().expect("failed to get vCPU events");
+    assert_eq!(events.exception(), Some(exception));
+    assert!(events.nmi_pending());
+    assert!(events.interrupt_shadow().sti);
+    assert!(!events.interrupt_shadow().mov_ss);
+}
+
+#[test]
+fn debugregs() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    let vcpu = vm.create_vcpu().expect("failed to create vCPU");
+
+    let mut debugregs = vcpu.get_debugregs().expect("failed to get debug registers");
+    assert_eq!(debugregs.dr6, Dr6::default());
+    assert_eq!(debugregs.dr7, Dr7::default());
+
+    debugregs.db[1] = 0x2000;
+    debugregs
+        .dr7
+        .set_breakpoint(1, BreakpointCondition::Write, BreakpointLength::Four);
+    vcpu.set_debugregs(&debugregs)
+        .expect("failed to set debug registers");
+
+    let debugregs = vcpu.get_debugregs().expect("failed to get debug registers");
+    assert_eq!(debugregs.db[1], 0x2000);
+    assert!(debugregs.dr7.is_global_enabled(1));
+    assert!(!debugregs.dr7.is_local_enabled(1));
+    assert!(!debugregs.dr7.is_enabled(0));
+    assert_eq!(debugregs.dr7.condition(1), BreakpointCondition::Write);
+    assert_eq!(debugregs.dr7.length(1), BreakpointLength::Four);
+
+    let dr6 = Dr6::from_bits(Dr6::RESET | 0x4002);
+    assert_eq!(dr6.breakpoints_hit(), vec![1]);
+    assert!(dr6.is_single_step());
+    assert!(!dr6.is_bus_lock());
+}
+
+#[test]
+fn xsave_state() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    let vcpu = vm.create_vcpu().expect("failed to create vCPU");
+
+    let xcrs = vcpu.get_xcrs().expect("failed to get XCRs");
+    assert_eq!(xcrs.nr_xcrs, 1);
+    assert_eq!(xcrs.xcrs[0].xcr, 0);
+    assert!(XstateBv::from_bits(xcrs.xcrs[0].value).contains(XstateComponent::X87));
+    vcpu.set_xcrs(&xcrs).expect("failed to set XCRs");
+
+    // Set the x87 control word, and mark the x87 state as in use.
+    let mut xsave = vcpu.get_xsave().expect("failed to get XSAVE area");
+    xsave.region_mut()[0] = (xsave.region()[0] & !0xffff) | 0x27f;
+    xsave.region_mut()[128] |= 1;
+    vcpu.set_xsave(&xsave).expect("failed to set XSAVE area");
+
+    let xsave2 = vcpu.get_xsave2().expect("failed to get XSAVE2 area");
+    assert!(xsave2.size() >= 4096);
+    assert_eq!(xsave2.region()[0] & 0xffff, 0x27f);
+    assert!(xsave2.xstate_bv().contains(XstateComponent::X87));
+    vcpu.set_xsave2(&xsave2).expect("failed to set XSAVE2 area");
+}
+
+#[test]
+fn mp_state() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    vm.create_irq_chip().expect("failed to create IRQ chip");
+    let bsp = vm.create_vcpu_with_id(0).expect("failed to create vCPU");
+    let ap = vm.create_vcpu_with_id(1).expect("failed to create vCPU");
+
+    let state = bsp.get_mp_state().expect("failed to get MP state");
+    assert_eq!(state, MpState::Runnable);
+    let state = ap.get_mp_state().expect("failed to get MP state");
+    assert_eq!(state, MpState::Uninitialized);
+
+    ap.set_mp_state(MpState::Runnable)
+        .expect("failed to set MP state");
+    let state = ap.get_mp_state().expect("failed to get MP state");
+    assert_eq!(state, MpState::Runnable);
+    bsp.set_mp_state(MpState::Halted)
+        .expect("failed to set MP state");
+    let state = bsp.get_mp_state().expect("failed to get MP state");
+    assert_eq!(state, MpState::Halted);
+}
+
+#[test]
+fn guest_debug() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    let slot = MockSlot::new(0x10000).expect("failed to create memory region");
+
+    // nop; nop; nop; hlt
+    let code = [0x90, 0x90, 0x90, 0xf4];
+    let mut vcpu = setup_real_mode_guest(&vm, &slot, &code);
+
+    let mut debug = GuestDebug::new();
+    debug.single_step(true);
+    vcpu.set_guest_debug(&debug)
+        .expect("failed to enable single-step");
+    match vcpu.run_exit().expect("failed to run VCPU") {
+        VcpuExit::Debug(debug_exit) => {
+            assert!(debug_exit.is_single_step());
+            assert_eq!(debug_exit.pc, 0x1001);
+        }
+        exit => panic!("unexpected exit: {:?}", exit),
+    }
+
+    // Break before executing the hlt.
+    debug.single_step(false).hardware_breakpoint(
+        2,
+        0x1003,
+        BreakpointCondition::Execute,
+        BreakpointLength::One,
+    );
+    vcpu.set_guest_debug(&debug)
+        .expect("failed to set hardware breakpoint");
+    match vcpu.run_exit().expect("failed to run VCPU") {
+        VcpuExit::Debug(debug_exit) => {
+            assert!(!debug_exit.is_single_step());
+            assert_eq!(debug_exit.hardware_breakpoints(), vec![2]);
+            assert_eq!(debug_exit.pc, 0x1003);
+        }
+        exit => panic!("unexpected exit: {:?}", exit),
+    }
+
+    vcpu.set_guest_debug(&GuestDebug::disabled())
+        .expect("failed to disable guest debugging");
+    match vcpu.run_exit().expect("failed to run VCPU") {
+        VcpuExit::Hlt => {}
+        exit => panic!("unexpected exit: {:?}", exit),
+    }
+}
+
+#[test]
+fn translate() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    let slot = MockSlot::new(0x10000).expect("failed to create memory region");
+    let vcpu = setup_real_mode_guest(&vm, &slot, &[0xf4]);
+
+    // Without paging, virtual addresses map directly to physical ones.
+    let translation = vcpu.translate(0x5678).expect("failed to translate");
+    assert!(translation.valid);
+    assert_eq!(translation.physical_address, 0x5678);
+
+    // Map the 4MB page at 0x400000 to physical address 0, with a page
+    // directory at 0x2000.
+    const PAGE_DIR: u64 = 0x2000;
+    unsafe {
+        let pde = (slot.host_address() + PAGE_DIR + 4) as *mut u32;
+        *pde = 0x83;
+    }
+    let mut sregs = vcpu.get_kvm_sregs().expect("failed to get sregs");
+    sregs.cr3 = PAGE_DIR;
+    sregs.cr4 |= 1 << 4;
+    sregs.cr0 |= 1 | 1 << 31;
+    vcpu.set_kvm_sregs(&sregs).expect("failed to set sregs");
+
+    let translation = vcpu.translate(0x401234).expect("failed to translate");
+    assert!(translation.valid);
+    assert_eq!(translation.physical_address, 0x1234);
+    let translation = vcpu.translate(0x800000).expect("failed to translate");
+    assert!(!translation.valid);
+}
+
+#[test]
+fn userspace_interrupt() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    let slot = MockSlot::new(0x10000).expect("failed to create memory region");
+
+    // sti; nop; jmp $
+    let code = [0xfb, 0x90, 0xeb, 0xfe];
+    let mut vcpu = setup_real_mode_guest(&vm, &slot, &code);
+
+    // Point vector 0x20 at a handler doing: mov al, 0x20; out 0x10, al; hlt
+    let handler = [0xb0, 0x20, 0xe6, 0x10, 0xf4];
+    unsafe {
+        let ivt_entry = (slot.host_address() + 0x20 * 4) as *mut u32;
+        *ivt_entry = 0x1100;
+        std::ptr::copy_nonoverlapping(
+            handler.as_ptr(),
+            (slot.host_address() + 0x1100) as *mut u8,
+            handler.len(),
+        );
+    }
+
+    vcpu.request_interrupt_window(true);
+    match vcpu.run_exit().expect("failed to run VCPU") {
+        VcpuExit::IrqWindowOpen => {}
+        exit => panic!("unexpected exit: {:?}", exit),
+    }
+    assert!(vcpu.ready_for_interrupt_injection());
+    assert!(vcpu.if_flag());
+
+    vcpu.request_interrupt_window(false);
+    vcpu.interrupt(0x20).expect("failed to inject interrupt");
+    match vcpu.run_exit().expect("failed to run VCPU") {
+        VcpuExit::Io(io) => {
+            assert_eq!(io.port(), 0x10);
+            assert_eq!(io.data(), &[0x20]);
+        }
+        exit => panic!("unexpected exit: {:?}", exit),
+    }
+}
+
+#[test]
+fn nmi_smi_injection() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    let slot = MockSlot::new(0x10000).expect("failed to create memory region");
+
+    // jmp $
+    let code = [0xeb, 0xfe];
+    let mut vcpu = setup_real_mode_guest(&vm, &slot, &code);
+
+    // Point the NMI vector at a handler doing: mov al, 2; out 0x10, al; hlt
+    let handler = [0xb0, 0x02, 0xe6, 0x10, 0xf4];
+    unsafe {
+        let ivt_entry = (slot.host_address() + 2 * 4) as *mut u32;
+        *ivt_entry = 0x1100;
+        std::ptr::copy_nonoverlapping(
+            handler.as_ptr(),
+            (slot.host_address() + 0x1100) as *mut u8,
+            handler.len(),
+        );
+    }
+
+    vcpu.inject_nmi().expect("failed to inject NMI");
+    match vcpu.run_exit().expect("failed to run VCPU") {
+        VcpuExit::Io(io) => {
+            assert_eq!(io.port(), 0x10);
+            assert_eq!(io.data(), &[2]);
+        }
+        exit => panic!("unexpected exit: {:?}", exit),
+    }
+
+    let smm_cap = sys.check_cap_x86_smm()
+        .expect("failed to check SMM capability");
+    if smm_cap > 0 {
+        vcpu.inject_smi().expect("failed to inject SMI");
+        let events = vcpu.get_vcpu_events().expect("failed to get vCPU events");
+        assert!(events.smm().pending);
+    } else {
+        let err = vcpu.inject_smi().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+    }
+}
+
+#[test]
+fn kick_vcpu() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    let slot = MockSlot::new(0x10000).expect("failed to create memory region");
+
+    // jmp $
+    let code = [0xeb, 0xfe];
+    let mut vcpu = setup_real_mode_guest(&vm, &slot, &code);
+    let handle = vcpu.handle();
+
+    // A kick before the run stops it before entering the guest.
+    handle.kick().expect("failed to kick vCPU");
+    match vcpu.run_exit().expect("failed to run VCPU") {
+        VcpuExit::Interrupted => {}
+        exit => panic!("unexpected exit: {:?}", exit),
+    }
+    handle.kick().expect("failed to kick vCPU");
+    let err = vcpu.run().expect_err("kicked run succeeded");
+    assert_eq!(err.kind(), ErrorKind::Interrupted);
+
+    // Keep the kick signal blocked outside of the guest.
+    let mut sigset: libc::sigset_t = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::sigemptyset(&mut sigset);
+        libc::sigaddset(&mut sigset, VcpuHandle::signal());
+        libc::pthread_sigmask(libc::SIG_BLOCK, &sigset, null_mut());
+        libc::sigemptyset(&mut sigset);
+    }
+    vcpu.set_signal_mask(Some(&sigset))
+        .expect("failed to set signal mask");
+
+    let kicker = handle.clone();
+    let thread = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        kicker.kick().expect("failed to kick vCPU");
+    });
+    match vcpu.run_exit().expect("failed to run VCPU") {
+        VcpuExit::Interrupted => {}
+        exit => panic!("unexpected exit: {:?}", exit),
+    }
+    thread.join().expect("failed to join kicker thread");
+
+    vcpu.set_signal_mask(None)
+        .expect("failed to clear signal mask");
+    unsafe {
+        libc::sigaddset(&mut sigset, VcpuHandle::signal());
+        libc::pthread_sigmask(libc::SIG_UNBLOCK, &sigset, null_mut());
+    }
+
+    // The handle outlives the vCPU, but no longer signals its thread.
+    drop(vcpu);
+    handle.kick().expect("failed to kick dropped vCPU");
+}
+
+#[test]
+fn enable_cap() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+
+    let invalid = [
+        VmCap::X2ApicApi(1 << 8),
+        VmCap::ExitHypercall(1),
+        VmCap::UserSpaceMsr(1 << 3),
+        VmCap::MaxVcpuId(0),
+    ];
+    for cap in invalid.iter() {
+        let err = vm.enable_cap(*cap).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    vm.enable_cap(VmCap::SplitIrqchip { ioapic_pins: 24 })
+        .expect("failed to enable split irqchip");
+    vm.enable_cap(VmCap::X2ApicApi(KVM_X2APIC_API_USE_32BIT_IDS))
+        .expect("failed to enable x2APIC API");
+    vm.enable_cap(VmCap::DisableQuirks(KVM_X86_QUIRK_LINT0_REENABLED as u64))
+        .expect("failed to disable quirks");
+    vm.enable_cap(VmCap::UserSpaceMsr(KVM_MSR_EXIT_REASON_UNKNOWN))
+        .expect("failed to enable user space MSR exits");
+    vm.enable_cap(VmCap::MaxVcpuId(2))
+        .expect("failed to set max vCPU ID");
+
+    assert!(vm.create_vcpu_with_id(2).is_err());
+    let vcpu = vm.create_vcpu_with_id(1).expect("failed to create VCPU");
+    vcpu.enable_cap(VcpuCap::EnforcePvFeatureCpuid(true))
+        .expect("failed to enforce PV feature CPUID");
+}
+
+#[test]
+fn user_space_msr() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let vm = sys.create_vm().expect("failed to create VM");
+    vm.enable_cap(VmCap::UserSpaceMsr(KVM_MSR_EXIT_REASON_UNKNOWN))
+        .expect("failed to enable user space MSR exits");
+    let slot = MockSlot::new(0x10000).expect("failed to create memory region");
+
+    // mov ecx, 0x1234; rdmsr; wrmsr; hlt
+    let code = [0x66, 0xb9, 0x34, 0x12, 0x00, 0x00, 0x0f, 0x32, 0x0f, 0x30, 0xf4];
+    let mut vcpu = setup_real_mode_guest(&vm, &slot, &code);
+
+    match vcpu.run_exit().expect("failed to run VCPU") {
+        VcpuExit::X86Rdmsr {
+            reason,
+            index,
+            data,
+            ..
+        } => {
+            assert_eq!(reason, KVM_MSR_EXIT_REASON_UNKNOWN);
+            assert_eq!(index, 0x1234);
+            *data = 0x1_0000_0002;
+        }
+        exit => panic!("unexpected exit: {:?}", exit),
+    }
+    match vcpu.run_exit().expect("failed to run VCPU") {
+        VcpuExit::X86Wrmsr { index, data, .. } => {
+            assert_eq!(index, 0x1234);
+            assert_eq!(data, 0x1_0000_0002);
+        }
+        exit => panic!("unexpected exit: {:?}", exit),
+    }
+    match vcpu.run_exit().expect("failed to run VCPU") {
+        VcpuExit::Hlt => {}
+        exit => panic!("unexpected exit: {:?}", exit),
+    }
+}
+
+#[test]
+fn capabilities() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    assert_eq!(Capability::from_raw(KVM_CAP_NR_MEMSLOTS), Capability::NrMemslots);
+    assert_eq!(Capability::Xsave2.to_raw(), KVM_CAP_XSAVE2);
+    assert_eq!(Capability::from_raw(4000), Capability::Other(4000));
+    assert_eq!(Capability::Other(4000).to_raw(), 4000);
+    assert_eq!(
+        sys.check_extension(Capability::Irqchip)
+            .expect("failed to check capability"),
+        sys.check_cap_irqchip().expect("failed to check capability")
+    );
+
+    let caps = sys.capabilities().expect("failed to check capabilities");
+    assert_eq!(caps.iter().count(), Capability::all().len());
+    assert!(caps.is_supported(Capability::UserMemory));
+    assert!(!caps.is_supported(Capability::Other(4000)));
+    assert!(caps.nr_memslots() > 0);
+    assert!(caps.max_vcpus() >= caps.nr_vcpus());
+    assert_eq!(
+        caps.get(Capability::NrMemslots),
+        sys.check_extension(Capability::NrMemslots)
+            .expect("failed to check capability")
+    );
+
+    let vm = sys.create_vm().expect("failed to create VM");
+    let nr_memslots = vm.check_extension(Capability::NrMemslots)
+        .expect("failed to check VM capability");
+    assert!(nr_memslots > 0);
+    let vm_caps = vm.capabilities().expect("failed to check VM capabilities");
+    assert!(vm_caps.is_supported(Capability::UserMemory));
+    assert_eq!(vm_caps.nr_memslots(), caps.nr_memslots());
+}
+
+#[test]
+fn device_attributes() {
+    let sys = KVMSystem::new().expect("failed to create KVM system ioctl");
+    let device_ctrl = sys.check_extension(Capability::DeviceCtrl)
+        .expect("failed to check capability");
+    assert!(device_ctrl > 0);
+
+    let vm = sys.create_vm().expect("failed to create VM");
+    let supported = vm.supports_device(DeviceType::Other(1000))
+        .expect("failed to check device type");
+    assert!(!supported);
+    let supported = vm.supports_device(DeviceType::Vfio)
+        .expect("failed to check device type");
+    if supported {
+        let vfio = vm.create_device(DeviceType::Vfio)
+            .expect("failed to create device");
+        assert_eq!(vfio.device_type(), DeviceType::Vfio);
+        let group_add = KVM_DEV_VFIO_GROUP_ADD as u64;
+        let has_attr = vfio.has_attr(KVM_DEV_VFIO_GROUP, group_add)
+            .expect("failed to check device attribute");
+        assert!(has_attr);
+        let has_attr = vfio.has_attr(1000, 0)
+            .expect("failed to check device attribute");
+        assert!(!has_attr);
+
+        let err = vfio.get_attr(1000, 0).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = vfio.set_attr(1000, 0, 0).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        // The group fd of -1 is passed to KVM, which rejects it.
+        let err = vfio.set_attr(KVM_DEV_VFIO_GROUP, group_add, u64::from(u32::MAX))
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+    }
+
+    let vcpu = vm.create_vcpu().expect("failed to create VCPU");
+    let err = vcpu.get_attr(1000, 0).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    let tsc_offset = KVM_VCPU_TSC_OFFSET as u64;
+    let has_attr = vcpu.has_attr(KVM_VCPU_TSC_CTRL, tsc_offset)
+        .expect("failed to check vCPU attribute");
+    if has_attr {
+        let offset = vcpu.get_attr(KVM_VCPU_TSC_CTRL, tsc_offset)
+            .expect("failed to get TSC offset");
+        vcpu.set_attr(KVM_VCPU_TSC_CTRL, tsc_offset, offset)
+            .expect("failed to set TSC offset");
+    }
+
+    let xcomp = KVM_X86_XCOMP_GUEST_SUPP as u64;
+    let has_attr = sys.has_attr(KVM_X86_GRP_SYSTEM, xcomp)
+        .expect("failed to check system attribute");
+    if has_attr {
+        let features = sys.supported_xcomp_features()
+            .expect("failed to get XCOMP features");
+        assert!(features.contains(XstateComponent::X87));
+        assert!(features.contains(XstateComponent::Sse));
+    }
+}
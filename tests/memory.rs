@@ -1,6 +1,6 @@
 extern crate libkvm;
 
-use libkvm::mem::MemorySlot;
+use libkvm::mem::{DirtyLog, MemorySlot, PAGE_SIZE};
 
 pub struct MockSlot {
     size: usize,
@@ -10,6 +10,10 @@ impl MockSlot {
     pub fn new() -> Self {
         MockSlot { size: 55 }
     }
+
+    pub fn with_size(size: usize) -> Self {
+        MockSlot { size: size }
+    }
 }
 
 impl MemorySlot for MockSlot {
@@ -39,3 +43,25 @@ fn create_kvm_memory_slot() {
     assert_eq!(slot.guest_address(), 33);
     assert_eq!(slot.host_address(), 44);
 }
+
+#[test]
+fn create_dirty_log() {
+    let log = DirtyLog::new(&MockSlot::new());
+    assert_eq!(log.num_pages(), 1);
+    assert_eq!(log.as_slice().len(), 1);
+
+    let mut log = DirtyLog::new(&MockSlot::with_size(PAGE_SIZE * 65));
+    assert_eq!(log.num_pages(), 65);
+    assert_eq!(log.as_slice().len(), 2);
+    assert_eq!(log.count(), 0);
+
+    log.as_mut_slice()[0] = 0x8000_0000_0000_0001;
+    log.as_mut_slice()[1] = 0x1;
+    assert_eq!(log.dirty_pages().collect::<Vec<_>>(), vec![0, 63, 64]);
+    assert!(log.is_dirty(64));
+    assert!(!log.is_dirty(65));
+    assert_eq!(
+        log.dirty_addresses().collect::<Vec<_>>(),
+        vec![33, 33 + 63 * PAGE_SIZE as u64, 33 + 64 * PAGE_SIZE as u64]
+    );
+}